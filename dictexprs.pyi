@@ -1,6 +1,9 @@
 from __future__ import annotations
 
-from typing import Any, Self
+import builtins
+from collections.abc import Iterable, Iterator
+from os import PathLike
+from typing import Any, Literal, NoReturn, Self, TypedDict
 
 type IntoExpr = Expr | str | int | float | bool | None
 
@@ -11,13 +14,79 @@ def struct() -> ExprStructNameSpace: ...
 def field(name: str) -> Expr: ...
 def merge(*exprs: Expr) -> Expr: ...
 def coalesce(*exprs: Expr) -> Expr: ...
+def concat_list(*exprs: IntoExpr) -> Expr: ...
+def struct_from(
+    fields: dict[str, IntoExpr] | None = None, /, **named: IntoExpr
+) -> Expr: ...
+def parse(query: str) -> Expr: ...
+def scan_json(path: str | PathLike[str]) -> JsonScan: ...
+def scan_ndjson(
+    path: str | PathLike[str], *, on_error: Literal["raise", "skip", "null"] = "raise"
+) -> NdjsonScan: ...
+
+class QueryError(ValueError):
+    expr: str | None
+    path: str | None
+
+class ParseError(QueryError):
+    line: int
+    column: int
+
+class TypeMismatchError(QueryError):
+    expected: str | None
+    actual: str | None
+
+class InvalidArgumentError(QueryError): ...
+class EvaluationLimitError(QueryError): ...
+
+class TraceEntry(TypedDict):
+    expr: str
+    depth: int
+    index: int | None
+    path: str | None
+    input: str
+    output: str
+    mismatch: bool
 
 class LazyQuery:
-    def collect(self) -> Any: ...
+    def collect(
+        self,
+        *,
+        strict: bool = False,
+        max_expr_depth: int | None = None,
+        max_data_depth: int | None = None,
+        max_evaluations: int | None = None,
+        max_output: int | None = None,
+        timeout: float | None = None,
+    ) -> Any: ...
+    def trace(self) -> builtins.list[TraceEntry]: ...
+    def __copy__(self) -> LazyQuery: ...
+    def __deepcopy__(self, memo: dict[int, Any]) -> LazyQuery: ...
 
 class DataJson:
-    def __init__(self, data: Any) -> None: ...
+    def __init__(self, data: Any, *, native: bool = False) -> None: ...
+    @staticmethod
+    def from_json(json: bytes | str) -> DataJson: ...
+    @staticmethod
+    def read_json(path: str | PathLike[str]) -> DataJson: ...
+    @property
+    def is_native(self) -> bool: ...
     def query(self, expr: Expr) -> LazyQuery: ...
+    def select(self, **named_exprs: Expr) -> dict[str, Any]: ...
+
+class JsonScan:
+    def query(self, expr: Expr) -> JsonQuery: ...
+
+class JsonQuery:
+    def __iter__(self) -> Iterator[Any]: ...
+    def collect(self) -> Any: ...
+
+class NdjsonScan:
+    def query(self, expr: Expr) -> NdjsonQuery: ...
+
+class NdjsonQuery:
+    def __iter__(self) -> Iterator[Any]: ...
+    def collect(self) -> builtins.list[Any]: ...
 
 class ExprListNameSpace:
     def get(self, i: int) -> Expr: ...
@@ -56,27 +125,80 @@ class ExprStrNameSpace:
         step: int | None = None,
     ) -> Expr: ...
 
+class ExprMetaNameSpace:
+    def eq(self, other: Expr) -> bool: ...
+    def hash(self) -> int: ...
+
 class ExprStructNameSpace:
     def field(self, name: str) -> Expr: ...
     def keys(self) -> Expr: ...
     def values(self) -> Expr: ...
 
 class Expr:
+    __hash__: None  # type: ignore[assignment]
+    def __bool__(self) -> NoReturn: ...
+    def __eq__(self, other: IntoExpr) -> Expr: ...  # type: ignore[override]
+    def __ne__(self, other: IntoExpr) -> Expr: ...  # type: ignore[override]
+    def __lt__(self, other: IntoExpr) -> Expr: ...
+    def __le__(self, other: IntoExpr) -> Expr: ...
+    def __gt__(self, other: IntoExpr) -> Expr: ...
+    def __ge__(self, other: IntoExpr) -> Expr: ...
+    def __and__(self, other: Expr) -> Expr: ...
+    def __or__(self, other: Expr) -> Expr: ...
+    def __invert__(self) -> Expr: ...
+    def __getitem__(self, key: int | slice | str) -> Expr: ...
+    def __add__(self, other: IntoExpr) -> Expr: ...
+    def __radd__(self, other: IntoExpr) -> Expr: ...
+    def __sub__(self, other: IntoExpr) -> Expr: ...
+    def __rsub__(self, other: IntoExpr) -> Expr: ...
+    def __mul__(self, other: IntoExpr) -> Expr: ...
+    def __rmul__(self, other: IntoExpr) -> Expr: ...
+    def __truediv__(self, other: IntoExpr) -> Expr: ...
+    def __rtruediv__(self, other: IntoExpr) -> Expr: ...
+    def __floordiv__(self, other: IntoExpr) -> Expr: ...
+    def __rfloordiv__(self, other: IntoExpr) -> Expr: ...
+    def __mod__(self, other: IntoExpr) -> Expr: ...
+    def __rmod__(self, other: IntoExpr) -> Expr: ...
+    def __pow__(self, other: IntoExpr) -> Expr: ...
+    def __rpow__(self, other: IntoExpr) -> Expr: ...
+    def __neg__(self) -> Expr: ...
+    def __copy__(self) -> Expr: ...
+    def __deepcopy__(self, memo: dict[int, Any]) -> Expr: ...
     @property
     def list(self) -> ExprListNameSpace: ...
     @property
     def str(self) -> ExprStrNameSpace: ...
     @property
     def struct(self) -> ExprStructNameSpace: ...
+    @property
+    def meta(self) -> ExprMetaNameSpace: ...
     def eq(self, other: IntoExpr) -> Self: ...
     def ne(self, other: IntoExpr) -> Self: ...
     def lt(self, other: IntoExpr) -> Self: ...
     def le(self, other: IntoExpr) -> Self: ...
     def gt(self, other: IntoExpr) -> Self: ...
     def ge(self, other: IntoExpr) -> Self: ...
+    def add(self, other: IntoExpr) -> Self: ...
+    def sub(self, other: IntoExpr) -> Self: ...
+    def mul(self, other: IntoExpr) -> Self: ...
+    def truediv(self, other: IntoExpr) -> Self: ...
+    def floordiv(self, other: IntoExpr) -> Self: ...
+    def mod(self, other: IntoExpr) -> Self: ...
+    def pow(self, other: IntoExpr) -> Self: ...
+    def neg(self) -> Self: ...
     def and_(self, other: Expr) -> Self: ...
     def or_(self, other: Expr) -> Self: ...
     def not_(self) -> Self: ...
+    def pipe(self, other: Expr) -> Self: ...
     def abs(self) -> Self: ...
     def ceil(self) -> Self: ...
     def floor(self) -> Self: ...
+    def to_jmespath(self) -> str: ...
+    def to_json_ast(self) -> str: ...
+    @staticmethod
+    def from_json_ast(json: bytes | str) -> Expr: ...
+    def optimize(self) -> Expr: ...
+    def explain(self, *, optimized: bool = False) -> str: ...
+    def search_many(
+        self, docs: Iterable[Any], *, parallel: bool = False
+    ) -> builtins.list[Any]: ...
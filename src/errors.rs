@@ -0,0 +1,94 @@
+use crate::explain;
+use crate::nodes::Node;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+
+create_exception!(
+    dictexprs,
+    QueryError,
+    PyValueError,
+    "Base class of the errors raised while parsing or evaluating a query."
+);
+create_exception!(
+    dictexprs,
+    ParseError,
+    QueryError,
+    "Raised when a JMESPath query string cannot be parsed."
+);
+create_exception!(
+    dictexprs,
+    TypeMismatchError,
+    QueryError,
+    "Raised when a value does not have the type an expression needs."
+);
+create_exception!(
+    dictexprs,
+    InvalidArgumentError,
+    QueryError,
+    "Raised when an expression is given an argument it cannot work with."
+);
+create_exception!(
+    dictexprs,
+    EvaluationLimitError,
+    QueryError,
+    "Raised when evaluating a query goes over one of its limits."
+);
+
+/// Attaches the failing expression and the path of its input to a query error.
+///
+/// Errors that already know where they come from are left untouched, so the innermost
+/// node that failed is the one reported.
+pub fn with_context(py: Python<'_>, err: PyErr, node: &Node, path: Option<String>) -> PyErr {
+    if !err.is_instance_of::<QueryError>(py) {
+        return err;
+    }
+    let value = err.value(py);
+    match value.getattr("expr") {
+        Ok(expr) if expr.is_none() => {}
+        _ => return err,
+    }
+    match value
+        .setattr("expr", explain::compact(py, node))
+        .and_then(|_| value.setattr("path", path))
+    {
+        Ok(()) => err,
+        Err(setattr_err) => setattr_err,
+    }
+}
+
+/// Whether the error comes from an evaluation step that did not report where it failed.
+pub fn lacks_context(py: Python<'_>, err: &PyErr) -> bool {
+    if err.is_instance_of::<QueryError>(py) {
+        matches!(err.value(py).getattr("expr"), Ok(expr) if expr.is_none())
+    } else {
+        err.is_instance_of::<PyTypeError>(py) || err.is_instance_of::<PyValueError>(py)
+    }
+}
+
+/// Turns an error raised by Python while evaluating `node` into a query error.
+pub fn from_eval(py: Python<'_>, err: PyErr, node: &Node, path: Option<String>) -> PyErr {
+    let wrapped = if err.is_instance_of::<QueryError>(py) {
+        err
+    } else if err.is_instance_of::<PyTypeError>(py) {
+        caused_by(
+            py,
+            TypeMismatchError::new_err(err.value(py).to_string()),
+            err,
+        )
+    } else if err.is_instance_of::<PyValueError>(py) {
+        caused_by(
+            py,
+            InvalidArgumentError::new_err(err.value(py).to_string()),
+            err,
+        )
+    } else {
+        return err;
+    };
+    with_context(py, wrapped, node, path)
+}
+
+fn caused_by(py: Python<'_>, err: PyErr, cause: PyErr) -> PyErr {
+    err.set_cause(py, Some(cause));
+    err
+}
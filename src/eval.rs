@@ -1,6 +1,9 @@
+use crate::errors::InvalidArgumentError;
+use crate::interrupt;
 use crate::matchs::match_any;
 use crate::nodes::{Bounded, EvalResult, Node, PyObjectWrapper};
 use pyo3::basic::CompareOp;
+use pyo3::exceptions::PyArithmeticError;
 use pyo3::prelude::*;
 use pyo3::types::*;
 pub mod pylibs {
@@ -32,19 +35,45 @@ fn not_eq(left: &Bound<'_, PyAny>, right: &Bound<'_, PyAny>) -> PyResult<bool> {
     Ok(!is_eq(left, right)?)
 }
 
+fn slice_bounds<'py>(
+    py: Python<'py>,
+    start: &Option<isize>,
+    end: &Option<isize>,
+    step: &Option<isize>,
+) -> Bound<'py, PySlice> {
+    let step = step.unwrap_or(1);
+    let (default_start, default_end) = if step < 0 {
+        (isize::MAX, isize::MIN)
+    } else {
+        (0, isize::MAX)
+    };
+    PySlice::new(
+        py,
+        start.unwrap_or(default_start),
+        end.unwrap_or(default_end),
+        step,
+    )
+}
+
 pub mod list {
     use super::*;
 
     pub enum SortKind {
-        SortBy,
-        MinBy,
-        MaxBy,
+        Sort,
+        Min,
+        Max,
     }
-    #[derive(PartialEq, PartialOrd)]
+    #[derive(PartialEq)]
     struct SortKey(Option<f64>);
 
     impl Eq for SortKey {}
 
+    impl PartialOrd for SortKey {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
     impl Ord for SortKey {
         fn cmp(&self, other: &Self) -> std::cmp::Ordering {
             self.0
@@ -75,12 +104,7 @@ pub mod list {
     ) -> EvalResult<'py> {
         Ok(list
             .as_any()
-            .get_item(PySlice::new(
-                py,
-                start.unwrap_or(0),
-                end.unwrap_or(isize::MAX),
-                step.unwrap_or(1),
-            ))?
+            .get_item(slice_bounds(py, start, end, step))?
             .into_any())
     }
 
@@ -104,6 +128,7 @@ pub mod list {
         let output = PyList::empty(py);
 
         for element in list.iter() {
+            interrupt::check(py)?;
             if match_any(py, cond, &element)?.is_truthy()? {
                 output.append(element)?;
             }
@@ -116,12 +141,45 @@ pub mod list {
         let output = PyList::empty(py);
 
         for element in list.iter() {
+            interrupt::check(py)?;
             output.append(match_any(py, key, &element)?)?;
         }
 
         Ok(output.into_any())
     }
 
+    pub fn filter_map<'py>(
+        py: Python<'py>,
+        list: &Bound<'py, PyList>,
+        cond: &Node,
+        key: &Node,
+    ) -> EvalResult<'py> {
+        let output = PyList::empty(py);
+
+        for element in list.iter() {
+            interrupt::check(py)?;
+            if match_any(py, cond, &element)?.is_truthy()? {
+                output.append(match_any(py, key, &element)?)?;
+            }
+        }
+
+        Ok(output.into_any())
+    }
+
+    pub fn project<'py>(py: Python<'py>, list: &Bound<'py, PyList>, key: &Node) -> EvalResult<'py> {
+        let output = PyList::empty(py);
+
+        for element in list.iter() {
+            interrupt::check(py)?;
+            let projected = match_any(py, key, &element)?;
+            if !projected.is_none() {
+                output.append(projected)?;
+            }
+        }
+
+        Ok(output.into_any())
+    }
+
     pub fn sort<'py>(py: Python<'py>, list: &Bound<'py, PyList>) -> EvalResult<'py> {
         pyo3::types::PyModule::import(py, pylibs::BUILTINS)?
             .getattr(pylibs::SORTED)?
@@ -138,6 +196,7 @@ pub mod list {
         let mut pairs: SortedVec = Vec::with_capacity(list.len());
 
         for element in list.iter() {
+            interrupt::check(py)?;
             let key_value = match_any(py, key, &element)?;
             let f = key_value.extract::<f64>().ok();
             let i = key_value.extract::<i64>().ok();
@@ -151,7 +210,7 @@ pub mod list {
         }
 
         match kind {
-            SortKind::SortBy => {
+            SortKind::Sort => {
                 pairs.sort_by(|a, b| {
                     (a.0, &a.1, a.2, a.3.as_deref()).cmp(&(b.0, &b.1, b.2, b.3.as_deref()))
                 });
@@ -161,7 +220,7 @@ pub mod list {
                 }
                 Ok(output.into_any())
             }
-            SortKind::MinBy => {
+            SortKind::Min => {
                 if let Some(min) = pairs.iter().min_by(|a, b| {
                     (a.0, &a.1, a.2, a.3.as_deref()).cmp(&(b.0, &b.1, b.2, b.3.as_deref()))
                 }) {
@@ -170,7 +229,7 @@ pub mod list {
                     Ok(py.None().into_bound(py))
                 }
             }
-            SortKind::MaxBy => {
+            SortKind::Max => {
                 if let Some(max) = pairs.iter().max_by(|a, b| {
                     (a.0, &a.1, a.2, a.3.as_deref()).cmp(&(b.0, &b.1, b.2, b.3.as_deref()))
                 }) {
@@ -183,15 +242,15 @@ pub mod list {
     }
 
     pub fn sort_by<'py>(py: Python<'py>, list: &Bound<'py, PyList>, key: &Node) -> EvalResult<'py> {
-        sort_like(py, list, key, SortKind::SortBy)
+        sort_like(py, list, key, SortKind::Sort)
     }
 
     pub fn min_by<'py>(py: Python<'py>, list: &Bound<'py, PyList>, key: &Node) -> EvalResult<'py> {
-        sort_like(py, list, key, SortKind::MinBy)
+        sort_like(py, list, key, SortKind::Min)
     }
 
     pub fn max_by<'py>(py: Python<'py>, list: &Bound<'py, PyList>, key: &Node) -> EvalResult<'py> {
-        sort_like(py, list, key, SortKind::MaxBy)
+        sort_like(py, list, key, SortKind::Max)
     }
 
     pub fn sum<'py>(py: Python<'py>, list: &Bound<'py, PyList>) -> EvalResult<'py> {
@@ -358,12 +417,7 @@ pub mod strs {
     ) -> EvalResult<'py> {
         Ok(string
             .as_any()
-            .get_item(PySlice::new(
-                py,
-                start.unwrap_or(0),
-                end.unwrap_or(isize::MAX),
-                step.unwrap_or(1),
-            ))?
+            .get_item(slice_bounds(py, start, end, step))?
             .into_any())
     }
 
@@ -413,6 +467,54 @@ pub fn cmp_bool<'py>(
     Ok(PyBool::new(py, result).to_owned().into_any())
 }
 
+pub fn arithmetic<'py>(
+    py: Python<'py>,
+    left: &Bounded<'py>,
+    right: &Bounded<'py>,
+    op: impl FnOnce(&Bounded<'py>, &Bounded<'py>) -> EvalResult<'py>,
+) -> EvalResult<'py> {
+    if !(is_number(left) && is_number(right)) {
+        return Ok(py.None().into_bound(py));
+    }
+    match op(left, right) {
+        Ok(result) if is_number(&result) => Ok(result),
+        Ok(_) => Ok(py.None().into_bound(py)),
+        Err(err) if err.is_instance_of::<PyArithmeticError>(py) => Ok(py.None().into_bound(py)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Python computes integer powers exactly, in a time that grows with the size of the result,
+/// so powers that would have more bits than this are refused.
+const MAX_POW_BITS: u64 = 1 << 16;
+
+/// Refuses the powers of an integer of `bits` bits that would have more than `MAX_POW_BITS`,
+/// the exponent being `None` when it does not even fit in 64 bits.
+pub fn check_pow(bits: u64, exponent: Option<u64>) -> PyResult<()> {
+    // The power is at least 2 to the `bits - 1` times the exponent.
+    let is_too_large = bits > 1
+        && exponent.is_none_or(|exponent| (bits - 1).saturating_mul(exponent) > MAX_POW_BITS);
+    if is_too_large {
+        return Err(InvalidArgumentError::new_err(format!(
+            "integer powers are limited to {} bits",
+            MAX_POW_BITS
+        )));
+    }
+    Ok(())
+}
+
+pub fn pow<'py>(py: Python<'py>, left: &Bounded<'py>, right: &Bounded<'py>) -> EvalResult<'py> {
+    if left.is_instance_of::<PyInt>() && right.is_instance_of::<PyInt>() && !right.lt(0)? {
+        let bits = left.call_method0("bit_length")?.extract::<u64>()?;
+        check_pow(bits, right.extract::<u64>().ok())?;
+    }
+    arithmetic(py, left, right, |l, r| l.pow(r, py.None()))
+}
+
+pub fn neg<'py>(number: &Bounded<'py>) -> EvalResult<'py> {
+    number.neg()
+}
+
 pub fn abs<'py>(py: Python<'py>, number: &Bounded<'py>) -> EvalResult<'py> {
     Ok(number.extract::<f64>()?.abs().into_pyobject(py)?.into_any())
 }
@@ -448,6 +550,36 @@ pub fn merge<'py>(py: Python<'py>, value: &Bounded<'py>, items: &[Node]) -> Eval
     Ok(output.into_any())
 }
 
+pub fn multi_list<'py>(py: Python<'py>, value: &Bounded<'py>, items: &[Node]) -> EvalResult<'py> {
+    if value.is_none() {
+        return Ok(py.None().into_bound(py));
+    }
+    let output = PyList::empty(py);
+
+    for item in items {
+        output.append(match_any(py, item, value)?)?;
+    }
+
+    Ok(output.into_any())
+}
+
+pub fn multi_hash<'py>(
+    py: Python<'py>,
+    value: &Bounded<'py>,
+    items: &[(String, Node)],
+) -> EvalResult<'py> {
+    if value.is_none() {
+        return Ok(py.None().into_bound(py));
+    }
+    let output = PyDict::new(py);
+
+    for (key, item) in items {
+        output.set_item(key, match_any(py, item, value)?)?;
+    }
+
+    Ok(output.into_any())
+}
+
 pub fn coalesce<'py>(py: Python<'py>, value: &Bounded<'py>, items: &[Node]) -> EvalResult<'py> {
     for item in items {
         let evaluated = match_any(py, item, value)?;
@@ -0,0 +1,219 @@
+use crate::nodes::{ArithmeticOp, ComparisonOp, ListOp, Node, ScalarOp, StrOp, StructOp};
+use pyo3::prelude::*;
+
+/// An operation as it is written with the Python API.
+struct Call<'n> {
+    name: &'static str,
+    /// Arguments that are not expressions, such as indices or field names.
+    params: Vec<String>,
+    base: Option<&'n Node>,
+    /// Expression arguments, along with the name of the parameter they are passed as.
+    args: Vec<(String, &'n Node)>,
+}
+
+impl<'n> Call<'n> {
+    fn function(name: &'static str, items: impl IntoIterator<Item = &'n Node>) -> Self {
+        Self {
+            name,
+            params: Vec::new(),
+            base: None,
+            args: items
+                .into_iter()
+                .map(|item| (String::new(), item))
+                .collect(),
+        }
+    }
+
+    fn method(name: &'static str, base: &'n Node) -> Self {
+        Self {
+            name,
+            params: Vec::new(),
+            base: Some(base),
+            args: Vec::new(),
+        }
+    }
+
+    fn param(mut self, param: String) -> Self {
+        self.params.push(param);
+        self
+    }
+
+    fn arg(mut self, name: &str, arg: &'n Node) -> Self {
+        self.args.push((name.to_string(), arg));
+        self
+    }
+}
+
+fn slice(start: &Option<isize>, end: &Option<isize>, step: &Option<isize>) -> Vec<String> {
+    [start, end, step]
+        .into_iter()
+        .map(|bound| bound.map_or("None".to_string(), |bound| bound.to_string()))
+        .collect()
+}
+
+fn call<'n>(py: Python<'_>, node: &'n Node) -> Call<'n> {
+    match node {
+        Node::This => Call::function("element", []),
+        Node::Literal(obj) => Call::function("lit", []).param(obj.describe(py)),
+        Node::Struct(base, StructOp::Field(name)) if matches!(**base, Node::This) => {
+            Call::function("field", []).param(format!("{:?}", name))
+        }
+        Node::And(a, b) => Call::method("and_", a).arg("other", b),
+        Node::Or(a, b) => Call::method("or_", a).arg("other", b),
+        Node::Not(x) => Call::method("not_", x),
+        Node::Coalesce(items) => Call::function("coalesce", items),
+        Node::Merge(items) => Call::function("merge", items),
+        Node::MultiList(items) => Call::function("concat_list", items),
+        Node::MultiHash(items) => {
+            let mut call = Call::function("struct_from", []);
+            call.args = items
+                .iter()
+                .map(|(name, item)| (format!("{:?}", name), item))
+                .collect();
+            call
+        }
+        Node::Pipe(base, then) => Call::method("pipe", base).arg("other", then),
+        Node::List(base, op) => match op {
+            ListOp::Index(i) => Call::method("list.get", base).param(i.to_string()),
+            ListOp::Slice { start, end, step } => Call {
+                params: slice(start, end, step),
+                ..Call::method("list.slice", base)
+            },
+            ListOp::Length => Call::method("list.length", base),
+            ListOp::Reverse => Call::method("list.reverse", base),
+            ListOp::Flatten => Call::method("list.flatten", base),
+            ListOp::Contains(other) => Call::method("list.contains", base).arg("other", other),
+            ListOp::Filter(cond) => Call::method("list.filter", base).arg("cond", cond),
+            ListOp::Map(expr) => Call::method("list.map", base).arg("expr", expr),
+            ListOp::FilterMap(cond, expr) => Call::method("list.filter_map", base)
+                .arg("cond", cond)
+                .arg("expr", expr),
+            ListOp::Join(glue) => Call::method("list.join", base).param(format!("{:?}", glue)),
+            ListOp::Sort => Call::method("list.sort", base),
+            ListOp::Max => Call::method("list.max", base),
+            ListOp::Min => Call::method("list.min", base),
+            ListOp::Sum => Call::method("list.sum", base),
+            ListOp::Avg => Call::method("list.avg", base),
+            ListOp::SortBy(key) => Call::method("list.sort_by", base).arg("key", key),
+            ListOp::MinBy(key) => Call::method("list.min_by", base).arg("key", key),
+            ListOp::MaxBy(key) => Call::method("list.max_by", base).arg("key", key),
+            ListOp::Project(expr) => Call::method("list.project", base).arg("expr", expr),
+        },
+        Node::Str(base, op) => match op {
+            StrOp::Slice { start, end, step } => Call {
+                params: slice(start, end, step),
+                ..Call::method("str.slice", base)
+            },
+            StrOp::Reverse => Call::method("str.reverse", base),
+            StrOp::Contains(s) => Call::method("str.contains", base).param(format!("{:?}", s)),
+            StrOp::StartsWith(s) => Call::method("str.starts_with", base).param(format!("{:?}", s)),
+            StrOp::EndsWith(s) => Call::method("str.ends_with", base).param(format!("{:?}", s)),
+            StrOp::Length => Call::method("str.length", base),
+        },
+        Node::Struct(base, op) => match op {
+            StructOp::Field(name) => {
+                Call::method("struct.field", base).param(format!("{:?}", name))
+            }
+            StructOp::Keys => Call::method("struct.keys", base),
+            StructOp::Values => Call::method("struct.values", base),
+        },
+        Node::Scalar(base, op) => match op {
+            ScalarOp::Abs => Call::method("abs", base),
+            ScalarOp::Ceil => Call::method("ceil", base),
+            ScalarOp::Floor => Call::method("floor", base),
+            ScalarOp::Neg => Call::method("neg", base),
+        },
+        Node::Compare(base, op) => {
+            let name = match op {
+                ComparisonOp::Eq(_) => "eq",
+                ComparisonOp::Ne(_) => "ne",
+                ComparisonOp::Lt(_) => "lt",
+                ComparisonOp::Le(_) => "le",
+                ComparisonOp::Gt(_) => "gt",
+                ComparisonOp::Ge(_) => "ge",
+            };
+            Call::method(name, base).arg("other", op.other())
+        }
+        Node::Arithmetic(base, op) => {
+            let name = match op {
+                ArithmeticOp::Add(_) => "add",
+                ArithmeticOp::Sub(_) => "sub",
+                ArithmeticOp::Mul(_) => "mul",
+                ArithmeticOp::TrueDiv(_) => "truediv",
+                ArithmeticOp::FloorDiv(_) => "floordiv",
+                ArithmeticOp::Mod(_) => "mod",
+                ArithmeticOp::Pow(_) => "pow",
+            };
+            Call::method(name, base).arg("other", op.other())
+        }
+    }
+}
+
+/// Renders the expression on a single line, the way it would be written in Python.
+pub fn compact(py: Python<'_>, node: &Node) -> String {
+    // Fused filters and projections have no method of their own, and are written with the
+    // calls they stand for.
+    match node {
+        Node::List(base, ListOp::FilterMap(cond, expr)) => {
+            return format!(
+                "{}.list.filter({}).list.map({})",
+                compact(py, base),
+                compact(py, cond),
+                compact(py, expr)
+            )
+        }
+        Node::List(base, ListOp::Project(expr)) => {
+            return format!(
+                "{}.list.map({}).list.filter(element().ne(lit(null)))",
+                compact(py, base),
+                compact(py, expr)
+            )
+        }
+        _ => {}
+    }
+    let call = call(py, node);
+    let mut args = call.params;
+    if let Node::MultiHash(_) = node {
+        let fields: Vec<String> = call
+            .args
+            .iter()
+            .map(|(name, item)| format!("{}: {}", name, compact(py, item)))
+            .collect();
+        args.push(format!("{{{}}}", fields.join(", ")));
+    } else {
+        args.extend(call.args.iter().map(|(_, arg)| compact(py, arg)));
+    }
+    match call.base {
+        Some(base) => format!("{}.{}({})", compact(py, base), call.name, args.join(", ")),
+        None => format!("{}({})", call.name, args.join(", ")),
+    }
+}
+
+/// Renders the expression as an indented tree, each operation above its base and arguments.
+pub fn tree(py: Python<'_>, node: &Node) -> String {
+    let mut output = String::new();
+    write_tree(py, node, "", 0, &mut output);
+    output
+}
+
+fn write_tree(py: Python<'_>, node: &Node, label: &str, depth: usize, output: &mut String) {
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    output.push_str(&"  ".repeat(depth));
+    if !label.is_empty() {
+        output.push_str(label);
+        output.push_str(": ");
+    }
+    let call = call(py, node);
+    output.push_str(call.name);
+    if !call.params.is_empty() || (call.base.is_none() && call.args.is_empty()) {
+        output.push_str(&format!("({})", call.params.join(", ")));
+    }
+    if let Some(base) = call.base {
+        write_tree(py, base, "", depth + 1, output);
+    }
+    for (name, arg) in &call.args {
+        write_tree(py, arg, name, depth + 1, output);
+    }
+}
@@ -1,38 +1,446 @@
-use crate::matchs::match_any;
-use crate::nodes;
+use crate::inspect::{Inspector, Location};
+use crate::interrupt;
+use crate::limits::Limits;
+use crate::matchs::{self, match_any, match_shared};
+use crate::optimize::{has_common_subexpressions, optimize};
 use crate::queries::Expr;
+use crate::value::{self, Interner, Value};
+use crate::{errors, json, native, nodes, state};
+use pyo3::exceptions::PyTypeError;
 use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList, PyString, PyTuple};
+use std::borrow::Cow;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+enum Document {
+    Python(Py<PyAny>),
+    Native(Value),
+}
+
+impl Document {
+    fn clone_ref(&self, py: Python<'_>) -> Self {
+        match self {
+            Self::Python(data) => Self::Python(data.clone_ref(py)),
+            Self::Native(data) => Self::Native(data.clone()),
+        }
+    }
+
+    /// Strict and traced evaluations run on Python objects, native documents are converted.
+    fn to_py<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        match self {
+            Self::Python(data) => Ok(data.bind(py).clone()),
+            Self::Native(data) => data.to_py(py),
+        }
+    }
+
+    fn deep_copy(&self, py: Python<'_>, memo: &Bound<'_, PyAny>) -> PyResult<Self> {
+        match self {
+            Self::Python(data) => Ok(Self::Python(
+                py.import("copy")?
+                    .getattr("deepcopy")?
+                    .call1((data.bind(py), memo))?
+                    .unbind(),
+            )),
+            // Native values are never mutated, so sharing them is enough.
+            Self::Native(data) => Ok(Self::Native(data.clone())),
+        }
+    }
+
+    fn check_depth(&self, py: Python<'_>, limits: &Limits) -> PyResult<()> {
+        match self {
+            Self::Python(data) => limits.check_py_data(data.bind(py)),
+            Self::Native(data) => limits.check_native_data(data),
+        }
+    }
+
+    fn locate(&self, py: Python<'_>, node: &nodes::Node, err: PyErr) -> PyErr {
+        if !errors::lacks_context(py, &err) {
+            return err;
+        }
+        match self.to_py(py) {
+            Ok(data) => locate(py, node, &data, err),
+            Err(_) => errors::from_eval(py, err, node, None),
+        }
+    }
+}
+
+/// Evaluates a query that failed once more with the inspector, to report which node failed
+/// and where its input lies in the document.
+///
+/// This only runs on failure, so that the fast evaluators do not have to track paths.
+fn locate(py: Python<'_>, node: &nodes::Node, data: &Bound<'_, PyAny>, err: PyErr) -> PyErr {
+    if !errors::lacks_context(py, &err) {
+        return err;
+    }
+    match Inspector::new(py, false, false).eval(node, data, &Location::root()) {
+        Err(located) => located,
+        Ok(_) => errors::from_eval(py, err, node, None),
+    }
+}
+
+/// Evaluates one expression against each document of an iterable.
+///
+/// With `parallel`, the documents are first converted to native values, then split
+/// between threads that evaluate them without holding the GIL. Documents that are not
+/// plain JSON, expressions holding literals that are not, and documents whose integer
+/// arithmetic overflows 64 bits are evaluated on Python objects instead, so that the
+/// results do not depend on `parallel`.
+pub fn search_many<'py>(
+    py: Python<'py>,
+    node: &nodes::Node,
+    docs: &Bound<'py, PyAny>,
+    parallel: bool,
+) -> PyResult<Bound<'py, PyList>> {
+    let output = PyList::empty(py);
+    let search = |doc: &Bound<'py, PyAny>| {
+        match_any(py, node, doc).map_err(|err| locate(py, node, doc, err))
+    };
+    if !parallel || !node.is_native() {
+        for doc in docs.try_iter()? {
+            output.append(search(&doc?)?)?;
+        }
+        return Ok(output);
+    }
+    let mut interner = Interner::default();
+    let documents = docs
+        .try_iter()?
+        .map(|doc| {
+            let doc = doc?;
+            let value = interner.convert(&doc).ok();
+            Ok((doc, value))
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    let natives: Vec<&Value> = documents
+        .iter()
+        .filter_map(|(_, value)| value.as_ref())
+        .collect();
+    let mut results = py
+        .detach(|| search_parallel(node, &natives))
+        .map_err(|err| errors::from_eval(py, err, node, None))?
+        .into_iter();
+    for (doc, value) in &documents {
+        let result = value.as_ref().and_then(|_| results.next().flatten());
+        output.append(match result {
+            Some(result) => result.to_py(py)?,
+            None => search(doc)?,
+        })?;
+    }
+    Ok(output)
+}
+
+/// The result of each document, or `None` when its integer arithmetic overflowed.
+fn search_parallel(node: &nodes::Node, documents: &[&Value]) -> PyResult<Vec<Option<Value>>> {
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let chunk_size = documents.len().div_ceil(threads).max(1);
+    let stop = Arc::new(AtomicBool::new(false));
+    let (done, finished) = mpsc::channel();
+    thread::scope(|scope| {
+        let workers: Vec<_> = documents
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let (stop, done) = (stop.clone(), done.clone());
+                scope.spawn(move || {
+                    let _done = done;
+                    interrupt::work(stop, || {
+                        chunk
+                            .iter()
+                            .map(|doc| {
+                                interrupt::check_detached()?;
+                                match native::match_any(node, doc) {
+                                    Ok(result) => Ok(Some(result.into_owned())),
+                                    Err(native::NativeError::Overflow) => Ok(None),
+                                    Err(err) => Err(err.into()),
+                                }
+                            })
+                            .collect::<PyResult<Vec<_>>>()
+                    })
+                })
+            })
+            .collect();
+        drop(done);
+        interrupt::wait(finished, &stop)?;
+        let mut results = Vec::with_capacity(documents.len());
+        for worker in workers {
+            let chunk = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+            results.extend(chunk);
+        }
+        Ok(results)
+    })
+}
 
 #[pyclass(module = "dictexprs", name = "LazyQuery")]
 pub struct LazyQuery {
-    data: Py<PyAny>,
+    data: Document,
+    /// The expression as written, which strict and traced evaluations run so that the
+    /// type mismatches folded away by the optimizer are still reported.
+    expr: nodes::Node,
     node: nodes::Node,
+    /// Whether some subexpressions are repeated, and are worth evaluating only once.
+    shared: bool,
+}
+
+impl LazyQuery {
+    fn new(py: Python<'_>, data: Document, expr: nodes::Node) -> PyResult<Self> {
+        let node = optimize(py, expr.clone())?;
+        Ok(Self {
+            data,
+            expr,
+            shared: has_common_subexpressions(&node),
+            node,
+        })
+    }
 }
 
 #[pymethods]
 impl LazyQuery {
-    pub fn collect(&self, py: Python<'_>) -> PyResult<Py<PyAny>> {
-        match_any(py, &self.node, self.data.bind(py)).map(|result| result.unbind())
+    #[pyo3(signature = (
+        *,
+        strict=false,
+        max_expr_depth=None,
+        max_data_depth=None,
+        max_evaluations=None,
+        max_output=None,
+        timeout=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn collect(
+        &self,
+        py: Python<'_>,
+        strict: bool,
+        max_expr_depth: Option<usize>,
+        max_data_depth: Option<usize>,
+        max_evaluations: Option<usize>,
+        max_output: Option<usize>,
+        timeout: Option<f64>,
+    ) -> PyResult<Py<PyAny>> {
+        let limits = Limits {
+            max_expr_depth,
+            max_data_depth,
+            max_evaluations,
+            max_output,
+        };
+        limits.check_expr(&self.node)?;
+        self.data.check_depth(py, &limits)?;
+        interrupt::watch(timeout, || {
+            // Counting evaluations and output is left to the inspector, so that the fast
+            // evaluators do not pay for it.
+            if strict || limits.is_budgeted() {
+                let data = self.data.to_py(py)?;
+                let node = if strict { &self.expr } else { &self.node };
+                let mut inspector = Inspector::new(py, strict, false).with_budget(limits.budget());
+                return Ok(inspector.eval(node, &data, &Location::root())?.0.unbind());
+            }
+            let result = match &self.data {
+                Document::Python(data) if self.shared => {
+                    match_shared(py, &self.node, data.bind(py), &mut matchs::Shared::new())
+                        .map(|result| result.unbind())
+                }
+                Document::Python(data) => {
+                    match_any(py, &self.node, data.bind(py)).map(|result| result.unbind())
+                }
+                Document::Native(data) => {
+                    let evaluated = py.detach(|| {
+                        if self.shared {
+                            native::match_shared(&self.node, data, &mut native::Shared::new())
+                                .map(Cow::into_owned)
+                        } else {
+                            native::match_any(&self.node, data).map(Cow::into_owned)
+                        }
+                    });
+                    match evaluated {
+                        Ok(result) => result.to_py(py).map(|result| result.unbind()),
+                        // Only Python integers hold the result exactly.
+                        Err(native::NativeError::Overflow) => {
+                            match_any(py, &self.node, &data.to_py(py)?)
+                                .map(|result| result.unbind())
+                        }
+                        Err(err) => Err(err.into()),
+                    }
+                }
+            };
+            result.map_err(|err| self.data.locate(py, &self.node, err))
+        })
+    }
+
+    /// Evaluates the query on Python objects, recording each node evaluated along the way.
+    pub fn trace<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let data = self.data.to_py(py)?;
+        let mut inspector = Inspector::new(py, false, true);
+        inspector.eval(&self.expr, &data, &Location::root())?;
+        inspector.into_list()
+    }
+
+    /// Pickled as the document and the expression it queries.
+    pub fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyTuple>> {
+        let data = Bound::new(
+            py,
+            DataJson {
+                data: self.data.clone_ref(py),
+            },
+        )?;
+        let expr = Expr {
+            node: self.expr.clone(),
+        };
+        (data.getattr("query")?, (expr,)).into_pyobject(py)
+    }
+
+    pub fn __copy__(&self, py: Python<'_>) -> Self {
+        Self {
+            data: self.data.clone_ref(py),
+            expr: self.expr.clone(),
+            node: self.node.clone(),
+            shared: self.shared,
+        }
+    }
+
+    pub fn __deepcopy__(&self, py: Python<'_>, memo: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let expr = state::load(
+            &py.import("copy")?
+                .getattr("deepcopy")?
+                .call1((state::dump(py, &self.expr)?, memo))?,
+        )?;
+        Self::new(py, self.data.deep_copy(py, memo)?, expr)
     }
 }
 
 #[pyclass(module = "dictexprs", name = "DataJson")]
 pub struct DataJson {
-    data: Py<PyAny>,
+    data: Document,
 }
 
 #[pymethods]
 impl DataJson {
     #[new]
-    pub fn new(data: &Bound<'_, PyAny>) -> Self {
-        DataJson {
-            data: data.clone().unbind(),
+    #[pyo3(signature = (data, *, native=false))]
+    pub fn new(data: &Bound<'_, PyAny>, native: bool) -> PyResult<Self> {
+        let data = if native {
+            Document::Native(value::from_py(data)?)
+        } else {
+            Document::Python(data.clone().unbind())
+        };
+        Ok(DataJson { data })
+    }
+
+    #[staticmethod]
+    pub fn from_json(py: Python<'_>, json: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let data = if let Ok(bytes) = json.cast::<PyBytes>() {
+            let bytes = bytes.as_bytes();
+            py.detach(|| json::parse(bytes))?
+        } else if let Ok(string) = json.cast::<PyString>() {
+            let string = string.to_str()?;
+            py.detach(|| json::parse(string.as_bytes()))?
+        } else {
+            return Err(PyTypeError::new_err(format!(
+                "from_json expects bytes or str, not {}",
+                json.get_type().name()?
+            )));
+        };
+        Ok(DataJson {
+            data: Document::Native(data),
+        })
+    }
+
+    #[staticmethod]
+    pub fn read_json(py: Python<'_>, path: PathBuf) -> PyResult<Self> {
+        let data = py.detach(|| json::parse(&json::read(&path)?))?;
+        Ok(DataJson {
+            data: Document::Native(data),
+        })
+    }
+
+    #[getter]
+    pub fn is_native(&self) -> bool {
+        matches!(self.data, Document::Native(_))
+    }
+
+    /// Native documents are pickled as Python objects, converted back when loaded.
+    pub fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyTuple>> {
+        let cls = py.get_type::<Self>().into_any();
+        match &self.data {
+            Document::Python(data) => (cls, (data.bind(py),)).into_pyobject(py),
+            Document::Native(data) => {
+                let kwargs = PyDict::new(py);
+                kwargs.set_item("native", true)?;
+                let constructor = py
+                    .import("functools")?
+                    .getattr("partial")?
+                    .call((cls,), Some(&kwargs))?;
+                (constructor, (data.to_py(py)?,)).into_pyobject(py)
+            }
         }
     }
+
     pub fn query(&self, py: Python<'_>, expr: &Expr) -> PyResult<LazyQuery> {
-        Ok(LazyQuery {
-            data: self.data.clone_ref(py),
-            node: expr.node.clone(),
-        })
+        LazyQuery::new(py, self.data.clone_ref(py), expr.node.clone())
+    }
+
+    /// Evaluates several expressions at once, computing their common parts only once.
+    #[pyo3(signature = (**named_exprs))]
+    pub fn select<'py>(
+        &self,
+        py: Python<'py>,
+        named_exprs: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let mut names = Vec::new();
+        let mut nodes = Vec::new();
+        for (name, expr) in named_exprs.into_iter().flat_map(|dict| dict.iter()) {
+            names.push(name);
+            nodes.push(optimize(py, expr.extract::<PyRef<Expr>>()?.node.clone())?);
+        }
+        let output = PyDict::new(py);
+        match &self.data {
+            Document::Python(data) => self.select_py(&output, names, &nodes, data.bind(py))?,
+            Document::Native(data) => {
+                let evaluated = py.detach(|| {
+                    let mut shared = native::Shared::new();
+                    nodes
+                        .iter()
+                        .enumerate()
+                        .map(|(i, node)| {
+                            native::match_shared(node, data, &mut shared)
+                                .map(Cow::into_owned)
+                                .map_err(|err| (i, err))
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                });
+                match evaluated {
+                    Ok(results) => {
+                        for (name, result) in names.into_iter().zip(results) {
+                            output.set_item(name, result.to_py(py)?)?;
+                        }
+                    }
+                    // Only Python integers hold the results exactly.
+                    Err((_, native::NativeError::Overflow)) => {
+                        self.select_py(&output, names, &nodes, &data.to_py(py)?)?
+                    }
+                    Err((i, err)) => return Err(self.data.locate(py, &nodes[i], err.into())),
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+impl DataJson {
+    fn select_py<'py>(
+        &self,
+        output: &Bound<'py, PyDict>,
+        names: Vec<Bound<'py, PyAny>>,
+        nodes: &[nodes::Node],
+        data: &Bound<'py, PyAny>,
+    ) -> PyResult<()> {
+        let mut shared = matchs::Shared::new();
+        for (name, node) in names.into_iter().zip(nodes) {
+            let result = match_shared(data.py(), node, data, &mut shared)
+                .map_err(|err| self.data.locate(data.py(), node, err))?;
+            output.set_item(name, result)?;
+        }
+        Ok(())
     }
 }
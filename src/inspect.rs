@@ -0,0 +1,585 @@
+use crate::errors::{self, TypeMismatchError};
+use crate::eval;
+use crate::explain;
+use crate::interrupt;
+use crate::limits::Budget;
+use crate::matchs::match_any;
+use crate::nodes::{Bounded, ListOp, Node, StructOp};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString};
+use std::rc::Rc;
+
+pub(crate) enum Step<'n> {
+    Field(&'n str),
+    Index(usize),
+}
+
+/// A path from the root of the document, stored from its last step.
+pub(crate) struct Segment<'n> {
+    parent: Path<'n>,
+    step: Step<'n>,
+}
+
+/// `None` is the root of the document.
+type Path<'n> = Option<Rc<Segment<'n>>>;
+
+/// Where a value comes from in the document, as far as it can be followed.
+#[derive(Clone)]
+pub enum Location<'n> {
+    /// The value was computed by the query.
+    Unknown,
+    At(Path<'n>),
+    /// A list built by the query, from items found at these locations.
+    Items(Rc<Vec<Location<'n>>>),
+}
+
+impl<'n> Location<'n> {
+    pub fn root() -> Self {
+        Self::At(None)
+    }
+
+    fn then(&self, step: Step<'n>) -> Self {
+        match self {
+            Self::At(parent) => Self::At(Some(Rc::new(Segment {
+                parent: parent.clone(),
+                step,
+            }))),
+            _ => Self::Unknown,
+        }
+    }
+
+    fn field(&self, name: &'n str) -> Self {
+        self.then(Step::Field(name))
+    }
+
+    fn item(&self, i: usize) -> Self {
+        match self {
+            Self::Items(items) => items.get(i).cloned().unwrap_or(Self::Unknown),
+            _ => self.then(Step::Index(i)),
+        }
+    }
+
+    /// Renders the location as a JSON path such as `$.users[1].name`.
+    fn path(&self) -> Option<String> {
+        let Self::At(path) = self else {
+            return None;
+        };
+        let mut steps = Vec::new();
+        let mut current = path;
+        while let Some(segment) = current {
+            steps.push(&segment.step);
+            current = &segment.parent;
+        }
+        let mut output = String::from("$");
+        for step in steps.into_iter().rev() {
+            match step {
+                Step::Field(name) if is_identifier(name) => {
+                    output.push('.');
+                    output.push_str(name);
+                }
+                Step::Field(name) => output.push_str(&format!("[{:?}]", name)),
+                Step::Index(i) => output.push_str(&format!("[{}]", i)),
+            }
+        }
+        Some(output)
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The JSON name of the type of a value, or its Python name if it has none.
+pub fn type_name(value: &Bounded<'_>) -> String {
+    if value.is_none() {
+        "null"
+    } else if value.is_instance_of::<PyBool>() {
+        "boolean"
+    } else if value.is_instance_of::<PyInt>() || value.is_instance_of::<PyFloat>() {
+        "number"
+    } else if value.is_instance_of::<PyString>() {
+        "string"
+    } else if value.is_instance_of::<PyList>() {
+        "array"
+    } else if value.is_instance_of::<PyDict>() {
+        "object"
+    } else {
+        return value
+            .get_type()
+            .name()
+            .map_or_else(|_| "unknown".to_string(), |name| name.to_string());
+    }
+    .to_string()
+}
+
+fn plural(prefix: &str, count: usize, noun: &str) -> String {
+    let suffix = if count == 1 { "" } else { "s" };
+    format!("{} {} {}{}", prefix, count, noun, suffix)
+}
+
+fn summary(value: &Bounded<'_>) -> PyResult<String> {
+    const MAX_LEN: usize = 60;
+    if let Ok(list) = value.cast::<PyList>() {
+        return Ok(plural("array of", list.len(), "item"));
+    }
+    if let Ok(dict) = value.cast::<PyDict>() {
+        return Ok(plural("object with", dict.len(), "key"));
+    }
+    let repr = value.repr()?.to_string();
+    Ok(match repr.char_indices().nth(MAX_LEN) {
+        Some((end, _)) => format!("{}...", &repr[..end]),
+        None => repr,
+    })
+}
+
+/// The first item that makes an aggregation return null, and the type it should have had.
+fn offending_item(op: &ListOp, list: &Bound<'_, PyList>) -> Option<(usize, &'static str)> {
+    let is_string = |item: &Bounded<'_>| item.is_instance_of::<PyString>();
+    let first = |expected: &'static str, is_valid: &dyn Fn(&Bounded<'_>) -> bool| {
+        list.iter()
+            .position(|item| !is_valid(&item))
+            .map(|i| (i, expected))
+    };
+    match op {
+        ListOp::Sum | ListOp::Avg => first("number", &eval::is_number),
+        ListOp::Join(_) => first("string", &is_string),
+        ListOp::Min | ListOp::Max => match list.iter().next() {
+            Some(item) if eval::is_number(&item) => first("number", &eval::is_number),
+            Some(item) if is_string(&item) => first("string", &is_string),
+            Some(_) => Some((0, "number or string")),
+            None => None,
+        },
+        _ => None,
+    }
+}
+
+/// One evaluated node, with what it was given and what it returned.
+struct Entry {
+    expr: String,
+    depth: usize,
+    /// The position of the list element being evaluated, inside a list operation.
+    index: Option<usize>,
+    path: Option<String>,
+    input: String,
+    output: String,
+    /// Whether the output is a null produced because a value had the wrong type.
+    mismatch: bool,
+}
+
+/// Evaluates an expression like `match_any`, following where each value comes from so that
+/// type mismatches can be reported, and optionally recording every node on the way.
+pub struct Inspector<'py> {
+    py: Python<'py>,
+    /// Raise a `TypeMismatchError` instead of returning null on a value of the wrong type.
+    strict: bool,
+    /// The nodes evaluated so far, when tracing.
+    entries: Option<Vec<Entry>>,
+    /// The evaluations and output left before the query is aborted.
+    budget: Budget,
+    depth: usize,
+    index: Option<usize>,
+}
+
+type Inspected<'py, 'n> = (Bounded<'py>, Location<'n>);
+
+impl<'py> Inspector<'py> {
+    pub fn new(py: Python<'py>, strict: bool, trace: bool) -> Self {
+        Self {
+            py,
+            strict,
+            entries: trace.then(Vec::new),
+            budget: Budget::default(),
+            depth: 0,
+            index: None,
+        }
+    }
+
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn eval<'n>(
+        &mut self,
+        node: &'n Node,
+        input: &Bounded<'py>,
+        location: &Location<'n>,
+    ) -> PyResult<Inspected<'py, 'n>> {
+        if let Node::This = node {
+            return Ok((input.clone(), location.clone()));
+        }
+        interrupt::check(self.py)?;
+        self.budget
+            .evaluate()
+            .map_err(|err| errors::with_context(self.py, err, node, location.path()))?;
+        let Some(entries) = &mut self.entries else {
+            let (result, location, _) = self
+                .eval_node(node, input, location)
+                .map_err(|err| errors::from_eval(self.py, err, node, location.path()))?;
+            return Ok((result, location));
+        };
+        let slot = entries.len();
+        entries.push(Entry {
+            expr: explain::compact(self.py, node),
+            depth: self.depth,
+            index: self.index,
+            path: location.path(),
+            input: type_name(input),
+            output: String::new(),
+            mismatch: false,
+        });
+        self.depth += 1;
+        let evaluated = self.eval_node(node, input, location);
+        self.depth -= 1;
+        let (result, location, mismatch) =
+            evaluated.map_err(|err| errors::from_eval(self.py, err, node, location.path()))?;
+        if let Some(entries) = &mut self.entries {
+            entries[slot].output = summary(&result)?;
+            entries[slot].mismatch = mismatch;
+        }
+        Ok((result, location))
+    }
+
+    fn eval_item<'n>(
+        &mut self,
+        node: &'n Node,
+        list: &Location<'n>,
+        i: usize,
+        item: &Bounded<'py>,
+    ) -> PyResult<Inspected<'py, 'n>> {
+        let index = self.index.replace(i);
+        let evaluated = self.eval(node, item, &list.item(i));
+        self.index = index;
+        evaluated
+    }
+
+    /// Returns the null a wrongly typed value turns into, or raises in strict mode.
+    fn mismatch<'n>(
+        &self,
+        node: &Node,
+        expected: &str,
+        value: &Bounded<'py>,
+        location: &Location<'n>,
+    ) -> PyResult<(Bounded<'py>, Location<'n>, bool)> {
+        if self.strict {
+            let actual = type_name(value);
+            let path = location.path();
+            let expr = explain::compact(self.py, node);
+            let err = TypeMismatchError::new_err(match &path {
+                Some(path) => format!(
+                    "expected {}, got {} at {} in {}",
+                    expected, actual, path, expr
+                ),
+                None => format!("expected {}, got {} in {}", expected, actual, expr),
+            });
+            let value = err.value(self.py);
+            value.setattr("expr", expr)?;
+            value.setattr("path", path)?;
+            value.setattr("expected", expected)?;
+            value.setattr("actual", actual)?;
+            return Err(err);
+        }
+        Ok((self.py.None().into_bound(self.py), Location::Unknown, true))
+    }
+
+    fn eval_node<'n>(
+        &mut self,
+        node: &'n Node,
+        input: &Bounded<'py>,
+        location: &Location<'n>,
+    ) -> PyResult<(Bounded<'py>, Location<'n>, bool)> {
+        let py = self.py;
+        let null = || (py.None().into_bound(py), Location::Unknown, false);
+        let computed = |result: Bounded<'py>| (result, Location::Unknown, false);
+        // An operation that fails is reported at the value it was applied to.
+        let failed = |err: PyErr, at: &Location<'n>| errors::from_eval(py, err, node, at.path());
+        Ok(match node {
+            Node::This | Node::Literal(_) => computed(match_any(py, node, input)?),
+            Node::And(a, b) => {
+                let (left, left_location) = self.eval(a, input, location)?;
+                if left.is_truthy()? {
+                    let (right, right_location) = self.eval(b, input, location)?;
+                    (right, right_location, false)
+                } else {
+                    (left, left_location, false)
+                }
+            }
+            Node::Or(a, b) => {
+                let (left, left_location) = self.eval(a, input, location)?;
+                if left.is_truthy()? {
+                    (left, left_location, false)
+                } else {
+                    let (right, right_location) = self.eval(b, input, location)?;
+                    (right, right_location, false)
+                }
+            }
+            Node::Not(x) => {
+                let result = !self.eval(x, input, location)?.0.is_truthy()?;
+                computed(PyBool::new(py, result).to_owned().into_any())
+            }
+            Node::Coalesce(items) => {
+                for item in items {
+                    let (evaluated, item_location) = self.eval(item, input, location)?;
+                    if !evaluated.is_none() {
+                        return Ok((evaluated, item_location, false));
+                    }
+                }
+                null()
+            }
+            Node::Merge(items) => {
+                let output = PyDict::new(py);
+                for item in items {
+                    let (evaluated, item_location) = self.eval(item, input, location)?;
+                    match evaluated.cast::<PyDict>() {
+                        Ok(dict) => {
+                            self.budget.produce(dict.len())?;
+                            output.update(dict.as_mapping())?
+                        }
+                        Err(_) if evaluated.is_none() => return Ok(null()),
+                        Err(_) => return self.mismatch(node, "object", &evaluated, &item_location),
+                    }
+                }
+                computed(output.into_any())
+            }
+            Node::MultiList(items) => {
+                if input.is_none() {
+                    return Ok(null());
+                }
+                let output = PyList::empty(py);
+                let mut locations = Vec::with_capacity(items.len());
+                for item in items {
+                    let (evaluated, item_location) = self.eval(item, input, location)?;
+                    self.budget.produce(1)?;
+                    output.append(evaluated)?;
+                    locations.push(item_location);
+                }
+                (output.into_any(), Location::Items(locations.into()), false)
+            }
+            Node::MultiHash(items) => {
+                if input.is_none() {
+                    return Ok(null());
+                }
+                let output = PyDict::new(py);
+                for (key, item) in items {
+                    let evaluated = self.eval(item, input, location)?.0;
+                    self.budget.produce(1)?;
+                    output.set_item(key, evaluated)?;
+                }
+                computed(output.into_any())
+            }
+            Node::List(base, op) => {
+                let (base, base_location) = self.eval(base, input, location)?;
+                match base.cast::<PyList>() {
+                    Ok(list) => self
+                        .eval_list(node, op, input, location, list, &base_location)
+                        .map_err(|err| failed(err, &base_location))?,
+                    Err(_) if base.is_none() => null(),
+                    Err(_) => return self.mismatch(node, "array", &base, &base_location),
+                }
+            }
+            Node::Str(base, op) => {
+                let (base, base_location) = self.eval(base, input, location)?;
+                match base.cast::<PyString>() {
+                    Ok(string) => computed(
+                        op.eval(py, string)
+                            .map_err(|err| failed(err, &base_location))?,
+                    ),
+                    Err(_) if base.is_none() => null(),
+                    Err(_) => return self.mismatch(node, "string", &base, &base_location),
+                }
+            }
+            Node::Struct(base, op) => {
+                let (base, base_location) = self.eval(base, input, location)?;
+                match base.cast::<PyDict>() {
+                    Ok(dict) => {
+                        let result = op
+                            .eval(py, dict)
+                            .map_err(|err| failed(err, &base_location))?;
+                        match op {
+                            StructOp::Field(name) => (result, base_location.field(name), false),
+                            _ => {
+                                self.budget.produce(dict.len())?;
+                                computed(result)
+                            }
+                        }
+                    }
+                    Err(_) if base.is_none() => null(),
+                    Err(_) => return self.mismatch(node, "object", &base, &base_location),
+                }
+            }
+            Node::Scalar(base, op) => {
+                let (base, base_location) = self.eval(base, input, location)?;
+                if eval::is_number(&base) {
+                    computed(
+                        op.eval(py, &base)
+                            .map_err(|err| failed(err, &base_location))?,
+                    )
+                } else if base.is_none() {
+                    null()
+                } else {
+                    return self.mismatch(node, "number", &base, &base_location);
+                }
+            }
+            Node::Compare(base, op) => {
+                let left = self.eval(base, input, location)?.0;
+                let right = self.eval(op.other(), input, location)?.0;
+                computed(op.apply(py, &left, &right)?)
+            }
+            Node::Arithmetic(base, op) => {
+                let left = self.eval(base, input, location)?;
+                let right = self.eval(op.other(), input, location)?;
+                for (operand, operand_location) in [&left, &right] {
+                    if !operand.is_none() && !eval::is_number(operand) {
+                        return self.mismatch(node, "number", operand, operand_location);
+                    }
+                }
+                computed(op.apply(py, &left.0, &right.0)?)
+            }
+            Node::Pipe(base, then) => {
+                let (base, base_location) = self.eval(base, input, location)?;
+                let (result, result_location) = self.eval(then, &base, &base_location)?;
+                (result, result_location, false)
+            }
+        })
+    }
+
+    fn eval_list<'n>(
+        &mut self,
+        node: &'n Node,
+        op: &'n ListOp,
+        input: &Bounded<'py>,
+        location: &Location<'n>,
+        list: &Bound<'py, PyList>,
+        list_location: &Location<'n>,
+    ) -> PyResult<(Bounded<'py>, Location<'n>, bool)> {
+        let py = self.py;
+        let output = PyList::empty(py);
+        let mut locations = Vec::new();
+        match op {
+            ListOp::Index(i) => {
+                let len = list.len() as isize;
+                let i = if *i < 0 { len + i } else { *i };
+                if (0..len).contains(&i) {
+                    let i = i as usize;
+                    return Ok((list.get_item(i)?, list_location.item(i), false));
+                }
+                return Ok((py.None().into_bound(py), Location::Unknown, false));
+            }
+            ListOp::Contains(search) => {
+                let search = self.eval(search, input, location)?.0;
+                return Ok((
+                    eval::list::contains(py, list, &search)?,
+                    Location::Unknown,
+                    false,
+                ));
+            }
+            ListOp::Flatten => {
+                for (i, item) in list.iter().enumerate() {
+                    match item.cast::<PyList>() {
+                        Ok(inner) => {
+                            let inner_location = list_location.item(i);
+                            for (j, inner_item) in inner.iter().enumerate() {
+                                self.budget.produce(1)?;
+                                output.append(inner_item)?;
+                                locations.push(inner_location.item(j));
+                            }
+                        }
+                        Err(_) => {
+                            self.budget.produce(1)?;
+                            output.append(item)?;
+                            locations.push(list_location.item(i));
+                        }
+                    }
+                }
+            }
+            ListOp::Filter(cond) => {
+                for (i, item) in list.iter().enumerate() {
+                    if self
+                        .eval_item(cond, list_location, i, &item)?
+                        .0
+                        .is_truthy()?
+                    {
+                        self.budget.produce(1)?;
+                        output.append(item)?;
+                        locations.push(list_location.item(i));
+                    }
+                }
+            }
+            ListOp::Map(key) => {
+                for (i, item) in list.iter().enumerate() {
+                    let (mapped, mapped_location) = self.eval_item(key, list_location, i, &item)?;
+                    self.budget.produce(1)?;
+                    output.append(mapped)?;
+                    locations.push(mapped_location);
+                }
+            }
+            ListOp::FilterMap(cond, key) => {
+                for (i, item) in list.iter().enumerate() {
+                    if self
+                        .eval_item(cond, list_location, i, &item)?
+                        .0
+                        .is_truthy()?
+                    {
+                        let (mapped, mapped_location) =
+                            self.eval_item(key, list_location, i, &item)?;
+                        self.budget.produce(1)?;
+                        output.append(mapped)?;
+                        locations.push(mapped_location);
+                    }
+                }
+            }
+            ListOp::Project(key) => {
+                for (i, item) in list.iter().enumerate() {
+                    let (projected, projected_location) =
+                        self.eval_item(key, list_location, i, &item)?;
+                    if !projected.is_none() {
+                        self.budget.produce(1)?;
+                        output.append(projected)?;
+                        locations.push(projected_location);
+                    }
+                }
+            }
+            _ => {
+                if let ListOp::SortBy(key) | ListOp::MinBy(key) | ListOp::MaxBy(key) = op {
+                    // The keys are only computed by `op` below, so they are walked beforehand
+                    // to be traced and counted.
+                    if self.entries.is_some() || self.budget.is_counting() {
+                        for (i, item) in list.iter().enumerate() {
+                            self.eval_item(key, list_location, i, &item)?;
+                        }
+                    }
+                }
+                let result = op.eval(py, input, list)?;
+                if let Ok(sorted) = result.cast::<PyList>() {
+                    self.budget.produce(sorted.len())?;
+                }
+                if result.is_none() {
+                    if let Some((i, expected)) = offending_item(op, list) {
+                        let item = list.get_item(i)?;
+                        return self.mismatch(node, expected, &item, &list_location.item(i));
+                    }
+                }
+                return Ok((result, Location::Unknown, false));
+            }
+        }
+        Ok((output.into_any(), Location::Items(locations.into()), false))
+    }
+
+    pub fn into_list(self) -> PyResult<Bound<'py, PyList>> {
+        let output = PyList::empty(self.py);
+        for entry in self.entries.into_iter().flatten() {
+            let dict = PyDict::new(self.py);
+            dict.set_item("expr", entry.expr)?;
+            dict.set_item("depth", entry.depth)?;
+            dict.set_item("index", entry.index)?;
+            dict.set_item("path", entry.path)?;
+            dict.set_item("input", entry.input)?;
+            dict.set_item("output", entry.output)?;
+            dict.set_item("mismatch", entry.mismatch)?;
+            output.append(dict)?;
+        }
+        Ok(output)
+    }
+}
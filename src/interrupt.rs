@@ -0,0 +1,137 @@
+use crate::errors::InvalidArgumentError;
+use pyo3::exceptions::{PyInterruptedError, PyTimeoutError};
+use pyo3::prelude::*;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How many list elements are evaluated between two checks, so that they stay cheap.
+const INTERVAL: u32 = 1024;
+
+/// How often the thread waiting for a parallel evaluation checks for signals.
+const POLL: Duration = Duration::from_millis(50);
+
+/// A query being collected on the current thread.
+#[derive(Clone, Copy)]
+struct Watch {
+    started: Instant,
+    timeout: Option<Duration>,
+}
+
+thread_local! {
+    static WATCH: Cell<Option<Watch>> = const { Cell::new(None) };
+    static COUNTDOWN: Cell<u32> = const { Cell::new(INTERVAL) };
+    /// Set on the threads of a parallel evaluation, once the thread waiting for them
+    /// was interrupted.
+    static STOP: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// Runs an evaluation that can be interrupted, and stopped once `timeout` seconds passed.
+pub fn watch<T>(timeout: Option<f64>, f: impl FnOnce() -> PyResult<T>) -> PyResult<T> {
+    let timeout = timeout
+        .map(|seconds| {
+            Some(seconds)
+                .filter(|seconds| *seconds > 0.0)
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .ok_or_else(|| {
+                    InvalidArgumentError::new_err(format!(
+                        "timeout must be a positive number of seconds, got {}",
+                        seconds
+                    ))
+                })
+        })
+        .transpose()?;
+    let previous = WATCH.replace(Some(Watch {
+        started: Instant::now(),
+        timeout,
+    }));
+    let result = f();
+    WATCH.set(previous);
+    result
+}
+
+fn is_due() -> bool {
+    let left = COUNTDOWN.get() - 1;
+    COUNTDOWN.set(if left == 0 { INTERVAL } else { left });
+    left == 0
+}
+
+fn check_timeout() -> PyResult<()> {
+    match WATCH.get() {
+        Some(Watch {
+            started,
+            timeout: Some(timeout),
+        }) if started.elapsed() > timeout => Err(PyTimeoutError::new_err(format!(
+            "query did not finish within {} seconds",
+            timeout.as_secs_f64()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn is_stopped() -> bool {
+    STOP.with_borrow(|stop| {
+        stop.as_ref()
+            .is_some_and(|stop| stop.load(Ordering::Relaxed))
+    })
+}
+
+/// Raises pending signals such as `KeyboardInterrupt`, or a `TimeoutError`, every so often.
+pub fn check(py: Python<'_>) -> PyResult<()> {
+    if !is_due() {
+        return Ok(());
+    }
+    py.check_signals()?;
+    check_timeout()
+}
+
+/// Same as `check`, for evaluations running without the GIL.
+///
+/// The GIL is only taken back for queries being collected, since signals are only handled
+/// on the thread that started them.
+pub fn check_detached() -> PyResult<()> {
+    if !is_due() {
+        return Ok(());
+    }
+    if is_stopped() {
+        return Err(PyInterruptedError::new_err(
+            "parallel evaluation was interrupted",
+        ));
+    }
+    if WATCH.get().is_none() {
+        return Ok(());
+    }
+    Python::attach(|py| py.check_signals())?;
+    check_timeout()
+}
+
+/// Runs the part of a parallel evaluation given to the current thread, which gives up
+/// once `stop` is set.
+pub fn work<T>(stop: Arc<AtomicBool>, f: impl FnOnce() -> PyResult<T>) -> PyResult<T> {
+    let previous = STOP.replace(Some(stop));
+    let result = f();
+    STOP.set(previous);
+    result
+}
+
+/// Waits for the threads of a parallel evaluation, which each hold a sender of `done` until
+/// they finish, while checking for signals on the calling thread since it is the only one
+/// receiving them. Once one arrives, `stop` tells the threads to give up.
+pub fn wait(done: Receiver<()>, stop: &AtomicBool) -> PyResult<()> {
+    loop {
+        match done.recv_timeout(POLL) {
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            Ok(()) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(err) =
+                    Python::attach(|py| py.check_signals()).and_then(|_| check_timeout())
+                {
+                    stop.store(true, Ordering::Relaxed);
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::projection::Projection;
+use crate::value::{Interner, Object, Value};
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use serde::de::{DeserializeSeed, Deserializer, Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::sync::Arc;
+
+pub fn parse(json: &[u8]) -> PyResult<Value> {
+    from_slice(json, &mut Interner::default(), &Projection::All).map_err(into_pyerr)
+}
+
+/// Parses a single document, sharing the interner between calls so that keys
+/// repeated across documents are only allocated once.
+///
+/// Subtrees outside of the projection are validated but never built.
+pub fn from_slice(
+    json: &[u8],
+    interner: &mut Interner,
+    projection: &Projection,
+) -> serde_json::Result<Value> {
+    let mut deserializer = serde_json::Deserializer::from_slice(json);
+    let value = ValueSeed(interner, projection).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    std::fs::read(path).map_err(|err| with_path(path, err))
+}
+
+/// Keeps the error kind, and thus the Python exception class, while naming the file.
+pub fn with_path(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
+
+pub fn into_pyerr(err: serde_json::Error) -> PyErr {
+    PyValueError::new_err(format!("invalid JSON: {}", err))
+}
+
+struct ValueSeed<'i, 'p>(&'i mut Interner, &'p Projection);
+
+impl<'de> DeserializeSeed<'de> for ValueSeed<'_, '_> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed<'_, '_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_unit<E: Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_bool<E: Error>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E: Error>(self, i: i64) -> Result<Value, E> {
+        Ok(Value::Int(i))
+    }
+
+    /// Integers beyond 64 bits are read as the nearest float, as serde_json does for the
+    /// ones that do not even fit in a `u64`.
+    fn visit_u64<E: Error>(self, u: u64) -> Result<Value, E> {
+        Ok(i64::try_from(u).map_or(Value::Float(u as f64), Value::Int))
+    }
+
+    fn visit_f64<E: Error>(self, f: f64) -> Result<Value, E> {
+        Ok(Value::Float(f))
+    }
+
+    fn visit_str<E: Error>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::Str(Arc::from(s)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(item) = seq.next_element_seed(ValueSeed(self.0, self.1))? {
+            items.push(item);
+        }
+        Ok(Value::List(Arc::new(items)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut object = Object::with_capacity(map.size_hint().unwrap_or_default());
+        while let Some(key) = map.next_key_seed(KeySeed(self.0))? {
+            match self.1.field(&key) {
+                Some(projection) => {
+                    let item = map.next_value_seed(ValueSeed(self.0, projection))?;
+                    object.insert(key, item);
+                }
+                None => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Value::Object(Arc::new(object)))
+    }
+}
+
+struct KeySeed<'i>(&'i mut Interner);
+
+impl<'de> DeserializeSeed<'de> for KeySeed<'_> {
+    type Value = Arc<str>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Arc<str>, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeySeed<'_> {
+    type Value = Arc<str>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object key")
+    }
+
+    fn visit_str<E: Error>(self, s: &str) -> Result<Arc<str>, E> {
+        Ok(self.0.intern(s))
+    }
+}
+
+enum ArrayState {
+    Start,
+    Next,
+    Done,
+}
+
+/// Splits a top-level JSON array into the raw bytes of its items without reading it whole.
+///
+/// Only the framing is checked here, each item is validated when it is parsed.
+pub struct ArrayItems<R> {
+    reader: R,
+    item: Vec<u8>,
+    state: ArrayState,
+}
+
+impl<R: BufRead> ArrayItems<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            item: Vec::new(),
+            state: ArrayState::Start,
+        }
+    }
+
+    pub fn next_item(&mut self) -> PyResult<Option<&[u8]>> {
+        match self.state {
+            ArrayState::Start => {
+                if self.peek()? != Some(b'[') {
+                    return Err(PyTypeError::new_err(
+                        "only a JSON array at the document root can be streamed",
+                    ));
+                }
+                self.reader.consume(1);
+                if self.peek()? == Some(b']') {
+                    return self.finish();
+                }
+            }
+            ArrayState::Next => match self.peek()? {
+                Some(b',') => self.reader.consume(1),
+                Some(b']') => return self.finish(),
+                Some(_) => return Err(invalid("expected ',' or ']' after an array item")),
+                None => return Err(invalid("EOF while parsing an array")),
+            },
+            ArrayState::Done => return Ok(None),
+        }
+        self.read_item()?;
+        self.state = ArrayState::Next;
+        Ok(Some(&self.item))
+    }
+
+    /// Skips whitespace and returns the next byte without consuming it.
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        loop {
+            let buffer = self.reader.fill_buf()?;
+            if buffer.is_empty() {
+                return Ok(None);
+            }
+            match buffer.iter().position(|b| !b.is_ascii_whitespace()) {
+                Some(position) => {
+                    let next = buffer[position];
+                    self.reader.consume(position);
+                    return Ok(Some(next));
+                }
+                None => {
+                    let len = buffer.len();
+                    self.reader.consume(len);
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self) -> PyResult<Option<&[u8]>> {
+        self.reader.consume(1);
+        self.state = ArrayState::Done;
+        match self.peek()? {
+            Some(_) => Err(invalid("trailing characters after the array")),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads up to the comma, bracket or whitespace ending the item, tracking strings and
+    /// nesting so that the delimiters inside the item are not mistaken for its end.
+    fn read_item(&mut self) -> io::Result<()> {
+        self.item.clear();
+        self.peek()?;
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        loop {
+            let buffer = self.reader.fill_buf()?;
+            if buffer.is_empty() {
+                return Ok(());
+            }
+            let mut end = None;
+            for (position, &byte) in buffer.iter().enumerate() {
+                if in_string {
+                    if escaped {
+                        escaped = false;
+                    } else if byte == b'\\' {
+                        escaped = true;
+                    } else if byte == b'"' {
+                        in_string = false;
+                    }
+                    continue;
+                }
+                match byte {
+                    b'"' => in_string = true,
+                    b'[' | b'{' => depth += 1,
+                    b']' | b'}' if depth > 0 => depth -= 1,
+                    _ if depth > 0 => {}
+                    b']' | b'}' | b',' => {
+                        end = Some(position);
+                        break;
+                    }
+                    _ if depth == 0 && byte.is_ascii_whitespace() => {
+                        end = Some(position);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let consumed = end.unwrap_or(buffer.len());
+            self.item.extend_from_slice(&buffer[..consumed]);
+            self.reader.consume(consumed);
+            if end.is_some() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn invalid(message: &str) -> PyErr {
+    PyValueError::new_err(format!("invalid JSON: {}", message))
+}
@@ -0,0 +1,365 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TokenKind {
+    Dot,
+    Star,
+    Current,
+    Comma,
+    Colon,
+    Pipe,
+    Or,
+    And,
+    Not,
+    Expref,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LBracket,
+    RBracket,
+    Flatten,
+    Filter,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Number(isize),
+    Identifier(String),
+    QuotedIdentifier(String),
+    RawString(String),
+    Literal(String),
+    Eof,
+}
+
+impl TokenKind {
+    pub fn binding_power(&self) -> u8 {
+        match self {
+            Self::Pipe => 1,
+            Self::Or => 2,
+            Self::And => 3,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge => 5,
+            Self::Flatten => 9,
+            Self::Star => 20,
+            Self::Filter => 21,
+            Self::Dot => 40,
+            Self::Not => 45,
+            Self::LBrace => 50,
+            Self::LBracket => 55,
+            Self::LParen => 60,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dot => write!(f, "'.'"),
+            Self::Star => write!(f, "'*'"),
+            Self::Current => write!(f, "'@'"),
+            Self::Comma => write!(f, "','"),
+            Self::Colon => write!(f, "':'"),
+            Self::Pipe => write!(f, "'|'"),
+            Self::Or => write!(f, "'||'"),
+            Self::And => write!(f, "'&&'"),
+            Self::Not => write!(f, "'!'"),
+            Self::Expref => write!(f, "'&'"),
+            Self::Eq => write!(f, "'=='"),
+            Self::Ne => write!(f, "'!='"),
+            Self::Lt => write!(f, "'<'"),
+            Self::Le => write!(f, "'<='"),
+            Self::Gt => write!(f, "'>'"),
+            Self::Ge => write!(f, "'>='"),
+            Self::LBracket => write!(f, "'['"),
+            Self::RBracket => write!(f, "']'"),
+            Self::Flatten => write!(f, "'[]'"),
+            Self::Filter => write!(f, "'[?'"),
+            Self::LBrace => write!(f, "'{{'"),
+            Self::RBrace => write!(f, "'}}'"),
+            Self::LParen => write!(f, "'('"),
+            Self::RParen => write!(f, "')'"),
+            Self::Number(n) => write!(f, "number {}", n),
+            Self::Identifier(name) => write!(f, "identifier '{}'", name),
+            Self::QuotedIdentifier(name) => write!(f, "quoted identifier {:?}", name),
+            Self::RawString(s) => write!(f, "raw string '{}'", s),
+            Self::Literal(s) => write!(f, "literal `{}`", s),
+            Self::Eof => write!(f, "end of expression"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Token {
+    pub kind: TokenKind,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SyntaxError {
+    pub message: String,
+    pub offset: usize,
+}
+
+impl SyntaxError {
+    pub fn new(message: impl Into<String>, offset: usize) -> Self {
+        Self {
+            message: message.into(),
+            offset,
+        }
+    }
+}
+
+pub(crate) type LexResult<T> = Result<T, SyntaxError>;
+
+pub(crate) fn tokenize(text: &str) -> LexResult<Vec<Token>> {
+    Lexer {
+        text,
+        chars: text.char_indices().collect(),
+        pos: 0,
+    }
+    .run()
+}
+
+struct Lexer<'a> {
+    text: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+}
+
+impl Lexer<'_> {
+    fn offset(&self) -> usize {
+        self.chars
+            .get(self.pos)
+            .map(|(offset, _)| *offset)
+            .unwrap_or(self.text.len())
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|(_, c)| *c)
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.pos + ahead).map(|(_, c)| *c)
+    }
+
+    fn run(mut self) -> LexResult<Vec<Token>> {
+        let mut tokens = Vec::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.pos += 1;
+                continue;
+            }
+            let offset = self.offset();
+            let kind = self.next_kind(c, offset)?;
+            tokens.push(Token { kind, offset });
+        }
+        tokens.push(Token {
+            kind: TokenKind::Eof,
+            offset: self.text.len(),
+        });
+        Ok(tokens)
+    }
+
+    fn one_or_two(&mut self, next: char, two: TokenKind, one: TokenKind) -> TokenKind {
+        if self.peek_at(1) == Some(next) {
+            self.pos += 2;
+            two
+        } else {
+            self.pos += 1;
+            one
+        }
+    }
+
+    fn next_kind(&mut self, c: char, offset: usize) -> LexResult<TokenKind> {
+        let simple = match c {
+            '.' => Some(TokenKind::Dot),
+            '*' => Some(TokenKind::Star),
+            '@' => Some(TokenKind::Current),
+            ',' => Some(TokenKind::Comma),
+            ':' => Some(TokenKind::Colon),
+            ']' => Some(TokenKind::RBracket),
+            '{' => Some(TokenKind::LBrace),
+            '}' => Some(TokenKind::RBrace),
+            '(' => Some(TokenKind::LParen),
+            ')' => Some(TokenKind::RParen),
+            _ => None,
+        };
+        if let Some(kind) = simple {
+            self.pos += 1;
+            return Ok(kind);
+        }
+        match c {
+            '[' => Ok(match self.peek_at(1) {
+                Some(']') => {
+                    self.pos += 2;
+                    TokenKind::Flatten
+                }
+                Some('?') => {
+                    self.pos += 2;
+                    TokenKind::Filter
+                }
+                _ => {
+                    self.pos += 1;
+                    TokenKind::LBracket
+                }
+            }),
+            '|' => Ok(self.one_or_two('|', TokenKind::Or, TokenKind::Pipe)),
+            '&' => Ok(self.one_or_two('&', TokenKind::And, TokenKind::Expref)),
+            '!' => Ok(self.one_or_two('=', TokenKind::Ne, TokenKind::Not)),
+            '<' => Ok(self.one_or_two('=', TokenKind::Le, TokenKind::Lt)),
+            '>' => Ok(self.one_or_two('=', TokenKind::Ge, TokenKind::Gt)),
+            '=' => {
+                if self.peek_at(1) == Some('=') {
+                    self.pos += 2;
+                    Ok(TokenKind::Eq)
+                } else {
+                    Err(SyntaxError::new("expected '==', found '='", offset))
+                }
+            }
+            '"' => self.quoted_identifier(offset),
+            '\'' => self.raw_string(offset),
+            '`' => self.literal(offset),
+            '-' | '0'..='9' => self.number(offset),
+            c if c.is_ascii_alphabetic() || c == '_' => Ok(self.identifier()),
+            other => Err(SyntaxError::new(
+                format!("unexpected character '{}'", other),
+                offset,
+            )),
+        }
+    }
+
+    fn identifier(&mut self) -> TokenKind {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                name.push(c);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        TokenKind::Identifier(name)
+    }
+
+    fn number(&mut self, offset: usize) -> LexResult<TokenKind> {
+        let mut digits = String::new();
+        if self.peek() == Some('-') {
+            digits.push('-');
+            self.pos += 1;
+        }
+        while let Some(c) = self.peek().filter(char::is_ascii_digit) {
+            digits.push(c);
+            self.pos += 1;
+        }
+        digits
+            .parse::<isize>()
+            .map(TokenKind::Number)
+            .map_err(|_| SyntaxError::new(format!("invalid number '{}'", digits), offset))
+    }
+
+    /// Consumes everything up to the closing `delim`, keeping escape sequences verbatim.
+    fn delimited(&mut self, delim: char, offset: usize) -> LexResult<String> {
+        self.pos += 1;
+        let mut content = String::new();
+        loop {
+            match self.peek() {
+                None => {
+                    return Err(SyntaxError::new(
+                        format!("unterminated {} delimited token", delim),
+                        offset,
+                    ))
+                }
+                Some('\\') if self.peek_at(1).is_some() => {
+                    content.push('\\');
+                    content.push(self.peek_at(1).unwrap_or_default());
+                    self.pos += 2;
+                }
+                Some(c) if c == delim => {
+                    self.pos += 1;
+                    return Ok(content);
+                }
+                Some(c) => {
+                    content.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn quoted_identifier(&mut self, offset: usize) -> LexResult<TokenKind> {
+        let raw = self.delimited('"', offset)?;
+        unescape_json(&raw)
+            .map(TokenKind::QuotedIdentifier)
+            .map_err(|message| SyntaxError::new(message, offset))
+    }
+
+    fn raw_string(&mut self, offset: usize) -> LexResult<TokenKind> {
+        let raw = self.delimited('\'', offset)?;
+        Ok(TokenKind::RawString(raw.replace("\\'", "'")))
+    }
+
+    fn literal(&mut self, offset: usize) -> LexResult<TokenKind> {
+        let raw = self.delimited('`', offset)?;
+        Ok(TokenKind::Literal(raw.replace("\\`", "`")))
+    }
+}
+
+fn unescape_json(raw: &str) -> Result<String, String> {
+    let mut output = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => output.push('"'),
+            Some('\\') => output.push('\\'),
+            Some('/') => output.push('/'),
+            Some('b') => output.push('\u{8}'),
+            Some('f') => output.push('\u{c}'),
+            Some('n') => output.push('\n'),
+            Some('r') => output.push('\r'),
+            Some('t') => output.push('\t'),
+            Some('u') => {
+                let high = read_hex(&mut chars)?;
+                let code = if (0xD800..0xDC00).contains(&high) {
+                    if chars.next() != Some('\\') || chars.next() != Some('u') {
+                        return Err("unpaired surrogate in quoted identifier".to_string());
+                    }
+                    let low = read_hex(&mut chars)?;
+                    0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+                } else {
+                    high
+                };
+                output
+                    .push(char::from_u32(code).ok_or_else(|| {
+                        "invalid unicode escape in quoted identifier".to_string()
+                    })?);
+            }
+            other => {
+                return Err(format!(
+                    "invalid escape sequence '\\{}' in quoted identifier",
+                    other.map(String::from).unwrap_or_default()
+                ))
+            }
+        }
+    }
+    Ok(output)
+}
+
+fn read_hex(chars: &mut std::str::Chars<'_>) -> Result<u32, String> {
+    let digits: String = chars.take(4).collect();
+    if digits.len() != 4 {
+        return Err("truncated unicode escape in quoted identifier".to_string());
+    }
+    u32::from_str_radix(&digits, 16).map_err(|_| {
+        format!(
+            "invalid unicode escape '\\u{}' in quoted identifier",
+            digits
+        )
+    })
+}
@@ -1,9 +1,24 @@
 use pyo3::prelude::*;
+mod errors;
 mod eval;
+mod explain;
 mod holder;
+mod inspect;
+mod interrupt;
+mod json;
+mod lexer;
+mod limits;
 mod matchs;
+mod native;
 mod nodes;
+mod optimize;
+mod parser;
+mod projection;
 mod queries;
+mod render;
+mod scan;
+mod state;
+mod value;
 use holder as hld;
 use queries as qry;
 
@@ -12,12 +27,40 @@ fn dictexprs(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<qry::Expr>()?;
     m.add_class::<hld::DataJson>()?;
     m.add_class::<hld::LazyQuery>()?;
+    m.add_class::<scan::NdjsonScan>()?;
+    m.add_class::<scan::NdjsonQuery>()?;
+    m.add_class::<scan::NdjsonIter>()?;
+    m.add_class::<scan::JsonScan>()?;
+    m.add_class::<scan::JsonQuery>()?;
+    m.add_class::<scan::JsonIter>()?;
     m.add_function(wrap_pyfunction!(qry::entryfuncs::lit, m)?)?;
     m.add_function(wrap_pyfunction!(qry::entryfuncs::element, m)?)?;
     m.add_function(wrap_pyfunction!(qry::entryfuncs::merge, m)?)?;
     m.add_function(wrap_pyfunction!(qry::entryfuncs::coalesce, m)?)?;
+    m.add_function(wrap_pyfunction!(qry::entryfuncs::struct_from, m)?)?;
+    m.add_function(wrap_pyfunction!(qry::entryfuncs::concat_list, m)?)?;
     m.add_function(wrap_pyfunction!(qry::entryfuncs::struct_, m)?)?;
     m.add_function(wrap_pyfunction!(qry::entryfuncs::list, m)?)?;
     m.add_function(wrap_pyfunction!(qry::entryfuncs::field, m)?)?;
+    m.add_function(wrap_pyfunction!(qry::entryfuncs::parse, m)?)?;
+    m.add_function(wrap_pyfunction!(scan::scan_json, m)?)?;
+    m.add_function(wrap_pyfunction!(scan::scan_ndjson, m)?)?;
+    let query_error = m.py().get_type::<errors::QueryError>();
+    query_error.setattr("expr", m.py().None())?;
+    query_error.setattr("path", m.py().None())?;
+    let type_mismatch_error = m.py().get_type::<errors::TypeMismatchError>();
+    type_mismatch_error.setattr("expected", m.py().None())?;
+    type_mismatch_error.setattr("actual", m.py().None())?;
+    m.add("QueryError", query_error)?;
+    m.add("ParseError", m.py().get_type::<errors::ParseError>())?;
+    m.add("TypeMismatchError", type_mismatch_error)?;
+    m.add(
+        "InvalidArgumentError",
+        m.py().get_type::<errors::InvalidArgumentError>(),
+    )?;
+    m.add(
+        "EvaluationLimitError",
+        m.py().get_type::<errors::EvaluationLimitError>(),
+    )?;
     Ok(())
 }
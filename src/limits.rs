@@ -0,0 +1,136 @@
+use crate::errors::EvaluationLimitError;
+use crate::nodes::Node;
+use crate::value::Value;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+/// How deeply any expression may be nested, so that the passes recursing over it, from the
+/// optimizer to the evaluators, stay within the stack of the threads running them.
+pub const MAX_EXPR_DEPTH: usize = 1000;
+
+/// Checks the depth of the expression without recursing, since it may be too deep
+/// for the stack in the first place.
+fn check_expr_depth(node: &Node, max: usize) -> PyResult<()> {
+    let mut stack = vec![(node, 1)];
+    while let Some((node, depth)) = stack.pop() {
+        if depth > max {
+            return Err(exceeded("max_expr_depth", max));
+        }
+        node.for_each_child(&mut |child| stack.push((child, depth + 1)));
+    }
+    Ok(())
+}
+
+/// Refuses the expressions nested deeper than `MAX_EXPR_DEPTH`, whatever the limits.
+pub fn check_expr(node: &Node) -> PyResult<()> {
+    check_expr_depth(node, MAX_EXPR_DEPTH)
+}
+
+/// The error raised for expressions nested deeper than `MAX_EXPR_DEPTH`.
+pub fn expr_too_deep() -> PyErr {
+    exceeded("max_expr_depth", MAX_EXPR_DEPTH)
+}
+
+/// Bounds on the work a single evaluation may do, none of them set by default.
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    pub max_expr_depth: Option<usize>,
+    pub max_data_depth: Option<usize>,
+    pub max_evaluations: Option<usize>,
+    pub max_output: Option<usize>,
+}
+
+fn exceeded(limit: &str, max: usize) -> PyErr {
+    EvaluationLimitError::new_err(format!("{} of {} exceeded", limit, max))
+}
+
+impl Limits {
+    /// Whether the evaluation must be counted while it runs, rather than checked up front.
+    pub fn is_budgeted(&self) -> bool {
+        self.max_evaluations.is_some() || self.max_output.is_some()
+    }
+
+    pub fn budget(&self) -> Budget {
+        Budget {
+            evaluations: self.max_evaluations.map(|max| (max, max)),
+            output: self.max_output.map(|max| (max, max)),
+        }
+    }
+
+    pub fn check_expr(&self, node: &Node) -> PyResult<()> {
+        match self.max_expr_depth {
+            Some(max) => check_expr_depth(node, max),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_py_data(&self, data: &Bound<'_, PyAny>) -> PyResult<()> {
+        let Some(max) = self.max_data_depth else {
+            return Ok(());
+        };
+        let mut stack = vec![(data.clone(), 0)];
+        while let Some((value, depth)) = stack.pop() {
+            if depth > max {
+                return Err(exceeded("max_data_depth", max));
+            }
+            if let Ok(list) = value.cast::<PyList>() {
+                stack.extend(list.iter().map(|item| (item, depth + 1)));
+            } else if let Ok(dict) = value.cast::<PyDict>() {
+                stack.extend(dict.values().iter().map(|item| (item, depth + 1)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check_native_data(&self, data: &Value) -> PyResult<()> {
+        let Some(max) = self.max_data_depth else {
+            return Ok(());
+        };
+        let mut stack = vec![(data, 0)];
+        while let Some((value, depth)) = stack.pop() {
+            if depth > max {
+                return Err(exceeded("max_data_depth", max));
+            }
+            match value {
+                Value::List(items) => stack.extend(items.iter().map(|item| (item, depth + 1))),
+                Value::Object(fields) => {
+                    stack.extend(fields.values().map(|item| (item, depth + 1)))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What is left of the limits counted during an evaluation, along with the limits themselves.
+#[derive(Default)]
+pub struct Budget {
+    evaluations: Option<(usize, usize)>,
+    output: Option<(usize, usize)>,
+}
+
+fn spend(budget: &mut Option<(usize, usize)>, amount: usize, limit: &str) -> PyResult<()> {
+    if let Some((left, max)) = budget {
+        *left = left
+            .checked_sub(amount)
+            .ok_or_else(|| exceeded(limit, *max))?;
+    }
+    Ok(())
+}
+
+impl Budget {
+    pub fn is_counting(&self) -> bool {
+        self.evaluations.is_some() || self.output.is_some()
+    }
+
+    /// Counts one node evaluated.
+    pub fn evaluate(&mut self) -> PyResult<()> {
+        spend(&mut self.evaluations, 1, "max_evaluations")
+    }
+
+    /// Counts list items or object entries built by the query.
+    pub fn produce(&mut self, elements: usize) -> PyResult<()> {
+        spend(&mut self.output, elements, "max_output")
+    }
+}
@@ -1,8 +1,11 @@
 use crate::eval;
-use crate::nodes::{Bounded, ComparisonOp, EvalResult, ListOp, Node, ScalarOp, StrOp, StructOp};
+use crate::nodes::{
+    ArithmeticOp, Bounded, ComparisonOp, EvalResult, ListOp, Node, ScalarOp, StrOp, StructOp,
+};
 use pyo3::basic::CompareOp;
 use pyo3::prelude::*;
 use pyo3::types::*;
+use std::collections::HashMap;
 
 pub fn match_any<'py>(py: Python<'py>, node: &Node, value: &Bounded<'py>) -> EvalResult<'py> {
     match node {
@@ -33,14 +36,93 @@ pub fn match_any<'py>(py: Python<'py>, node: &Node, value: &Bounded<'py>) -> Eva
             op.eval(py, &base_evaluated)
         }
         Node::Compare(base, op) => op.eval(py, value, &match_any(py, base, value)?),
+        Node::Arithmetic(base, op) => op.eval(py, value, &match_any(py, base, value)?),
+        Node::Pipe(base, then) => match_any(py, then, &match_any(py, base, value)?),
+        Node::MultiList(items) => eval::multi_list(py, value, items),
+        Node::MultiHash(items) => eval::multi_hash(py, value, items),
     }
 }
+/// Results of the subexpressions already evaluated against the root document.
+pub type Shared<'n, 'py> = HashMap<&'n Node, Bounded<'py>>;
+
+/// Same as `match_any` on the root document, but reuses the result of any identical
+/// subexpression evaluated earlier with the same cache.
+///
+/// Only the subexpressions evaluated against the root are cached (bases, operands and
+/// boolean branches), since the ones applied to list elements or piped values see a
+/// different input each time.
+pub fn match_shared<'n, 'py>(
+    py: Python<'py>,
+    node: &'n Node,
+    root: &Bounded<'py>,
+    shared: &mut Shared<'n, 'py>,
+) -> EvalResult<'py> {
+    if let Some(result) = shared.get(node) {
+        return Ok(result.clone());
+    }
+    let result = match node {
+        Node::List(base, op) => match match_shared(py, base, root, shared)?.cast::<PyList>() {
+            Ok(list) => op.eval(py, root, list)?,
+            Err(_) => py.None().into_bound(py),
+        },
+        Node::Str(base, op) => match match_shared(py, base, root, shared)?.cast::<PyString>() {
+            Ok(string) => op.eval(py, string)?,
+            Err(_) => py.None().into_bound(py),
+        },
+        Node::Struct(base, op) => match match_shared(py, base, root, shared)?.cast::<PyDict>() {
+            Ok(dict) => op.eval(py, dict)?,
+            Err(_) => py.None().into_bound(py),
+        },
+        Node::Scalar(base, op) => {
+            let base_evaluated = match_shared(py, base, root, shared)?;
+            if eval::is_number(&base_evaluated) {
+                op.eval(py, &base_evaluated)?
+            } else {
+                py.None().into_bound(py)
+            }
+        }
+        Node::Compare(base, op) => {
+            let left = match_shared(py, base, root, shared)?;
+            op.apply(py, &left, &match_shared(py, op.other(), root, shared)?)?
+        }
+        Node::Arithmetic(base, op) => {
+            let left = match_shared(py, base, root, shared)?;
+            op.apply(py, &left, &match_shared(py, op.other(), root, shared)?)?
+        }
+        Node::And(a, b) => {
+            let left = match_shared(py, a, root, shared)?;
+            if left.is_truthy()? {
+                match_shared(py, b, root, shared)?
+            } else {
+                left
+            }
+        }
+        Node::Or(a, b) => {
+            let left = match_shared(py, a, root, shared)?;
+            if left.is_truthy()? {
+                left
+            } else {
+                match_shared(py, b, root, shared)?
+            }
+        }
+        Node::Not(x) => {
+            let result = !match_shared(py, x, root, shared)?.is_truthy()?;
+            PyBool::new(py, result).to_owned().into_any()
+        }
+        Node::Pipe(base, then) => match_any(py, then, &match_shared(py, base, root, shared)?)?,
+        _ => match_any(py, node, root)?,
+    };
+    shared.insert(node, result.clone());
+    Ok(result)
+}
+
 impl ScalarOp {
     pub fn eval<'py>(&self, py: Python<'py>, number: &Bounded<'py>) -> EvalResult<'py> {
         match self {
             Self::Abs => eval::abs(py, number),
             Self::Ceil => eval::ceil(py, number),
             Self::Floor => eval::floor(py, number),
+            Self::Neg => eval::neg(number),
         }
     }
 }
@@ -63,6 +145,7 @@ impl ListOp {
             Self::Join(glue) => eval::list::join(py, list, glue),
             Self::Filter(cond) => eval::list::filter(py, list, cond),
             Self::Map(key) => eval::list::map(py, list, key),
+            Self::FilterMap(cond, key) => eval::list::filter_map(py, list, cond, key),
             Self::Sort => eval::list::sort(py, list),
             Self::Max => eval::list::min_max(py, list, true),
             Self::Min => eval::list::min_max(py, list, false),
@@ -71,6 +154,7 @@ impl ListOp {
             Self::SortBy(key) => eval::list::sort_by(py, list, key),
             Self::MinBy(key) => eval::list::min_by(py, list, key),
             Self::MaxBy(key) => eval::list::max_by(py, list, key),
+            Self::Project(key) => eval::list::project(py, list, key),
         }
     }
 }
@@ -101,38 +185,50 @@ impl ComparisonOp {
         py: Python<'py>,
         value: &Bounded<'py>,
         base_evaluated: &Bounded<'py>,
+    ) -> EvalResult<'py> {
+        self.apply(py, base_evaluated, &match_any(py, self.other(), value)?)
+    }
+
+    pub fn apply<'py>(
+        &self,
+        py: Python<'py>,
+        left: &Bounded<'py>,
+        right: &Bounded<'py>,
     ) -> EvalResult<'py> {
         match self {
-            Self::Eq(other_node) => {
-                eval::eq(py, base_evaluated, &match_any(py, other_node, value)?)
-            }
-            Self::Ne(other_node) => {
-                eval::ne(py, base_evaluated, &match_any(py, other_node, value)?)
-            }
-            Self::Lt(other_node) => eval::cmp_bool(
-                py,
-                base_evaluated,
-                &match_any(py, other_node, value)?,
-                CompareOp::Lt,
-            ),
-            Self::Le(other_node) => eval::cmp_bool(
-                py,
-                base_evaluated,
-                &match_any(py, other_node, value)?,
-                CompareOp::Le,
-            ),
-            Self::Gt(other_node) => eval::cmp_bool(
-                py,
-                base_evaluated,
-                &match_any(py, other_node, value)?,
-                CompareOp::Gt,
-            ),
-            Self::Ge(other_node) => eval::cmp_bool(
-                py,
-                base_evaluated,
-                &match_any(py, other_node, value)?,
-                CompareOp::Ge,
-            ),
+            Self::Eq(_) => eval::eq(py, left, right),
+            Self::Ne(_) => eval::ne(py, left, right),
+            Self::Lt(_) => eval::cmp_bool(py, left, right, CompareOp::Lt),
+            Self::Le(_) => eval::cmp_bool(py, left, right, CompareOp::Le),
+            Self::Gt(_) => eval::cmp_bool(py, left, right, CompareOp::Gt),
+            Self::Ge(_) => eval::cmp_bool(py, left, right, CompareOp::Ge),
+        }
+    }
+}
+impl ArithmeticOp {
+    pub fn eval<'py>(
+        &self,
+        py: Python<'py>,
+        value: &Bounded<'py>,
+        base_evaluated: &Bounded<'py>,
+    ) -> EvalResult<'py> {
+        self.apply(py, base_evaluated, &match_any(py, self.other(), value)?)
+    }
+
+    pub fn apply<'py>(
+        &self,
+        py: Python<'py>,
+        left: &Bounded<'py>,
+        right: &Bounded<'py>,
+    ) -> EvalResult<'py> {
+        match self {
+            Self::Add(_) => eval::arithmetic(py, left, right, |l, r| l.add(r)),
+            Self::Sub(_) => eval::arithmetic(py, left, right, |l, r| l.sub(r)),
+            Self::Mul(_) => eval::arithmetic(py, left, right, |l, r| l.mul(r)),
+            Self::TrueDiv(_) => eval::arithmetic(py, left, right, |l, r| l.div(r)),
+            Self::FloorDiv(_) => eval::arithmetic(py, left, right, |l, r| l.floor_div(r)),
+            Self::Mod(_) => eval::arithmetic(py, left, right, |l, r| l.rem(r)),
+            Self::Pow(_) => eval::pow(py, left, right),
         }
     }
 }
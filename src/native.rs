@@ -0,0 +1,880 @@
+use crate::errors::{InvalidArgumentError, TypeMismatchError};
+use crate::eval;
+use crate::interrupt;
+use crate::nodes::{
+    ArithmeticOp, ComparisonOp, ListOp, Node, PyObjectWrapper, ScalarOp, StrOp, StructOp,
+};
+use crate::value::{self, Object, Value};
+use pyo3::prelude::*;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Evaluated values borrow from the document or the expression whenever possible,
+/// so that walking into a field does not touch any reference count.
+pub type Evaluated<'a> = Cow<'a, Value>;
+pub type NativeResult<'a> = Result<Evaluated<'a>, NativeError>;
+
+/// Boxed so that the happy path only moves a pointer-sized error slot around.
+pub enum NativeError {
+    Py(Box<PyErr>),
+    /// Integer arithmetic went beyond 64 bits, which only Python integers hold exactly:
+    /// callers holding the document as Python objects evaluate it again on them.
+    Overflow,
+}
+
+impl From<PyErr> for NativeError {
+    fn from(err: PyErr) -> Self {
+        Self::Py(Box::new(err))
+    }
+}
+
+impl From<NativeError> for PyErr {
+    fn from(err: NativeError) -> Self {
+        match err {
+            NativeError::Py(err) => *err,
+            NativeError::Overflow => InvalidArgumentError::new_err(
+                "integer arithmetic overflows 64 bits, which native documents cannot hold exactly",
+            ),
+        }
+    }
+}
+
+fn owned<'a>(value: Value) -> NativeResult<'a> {
+    Ok(Cow::Owned(value))
+}
+
+fn detached<'a>(evaluated: Evaluated<'_>) -> Evaluated<'a> {
+    Cow::Owned(evaluated.into_owned())
+}
+
+pub fn match_any<'a>(node: &'a Node, value: &'a Value) -> NativeResult<'a> {
+    match node {
+        Node::This => Ok(Cow::Borrowed(value)),
+        Node::Literal(obj) => literal(obj),
+        Node::And(a, b) => {
+            let left = match_any(a, value)?;
+            if left.is_truthy() {
+                match_any(b, value)
+            } else {
+                Ok(left)
+            }
+        }
+        Node::Or(a, b) => {
+            let left = match_any(a, value)?;
+            if left.is_truthy() {
+                Ok(left)
+            } else {
+                match_any(b, value)
+            }
+        }
+        Node::Not(x) => owned(Value::Bool(!match_any(x, value)?.is_truthy())),
+        Node::Coalesce(items) => coalesce(value, items),
+        Node::Merge(items) => merge(value, items),
+        Node::List(base, op) => match match_any(base, value)? {
+            Cow::Borrowed(Value::List(list)) => op.eval_native(value, list),
+            Cow::Owned(Value::List(list)) => op.eval_native(value, &list).map(detached),
+            _ => owned(Value::Null),
+        },
+        Node::Str(base, op) => match match_any(base, value)?.as_ref() {
+            Value::Str(string) => op.eval_native(string).and_then(owned),
+            _ => owned(Value::Null),
+        },
+        Node::Struct(base, op) => match match_any(base, value)? {
+            Cow::Borrowed(Value::Object(map)) => Ok(op.eval_native(map)),
+            Cow::Owned(Value::Object(map)) => Ok(detached(op.eval_native(&map))),
+            _ => owned(Value::Null),
+        },
+        Node::Scalar(base, op) => {
+            let base_evaluated = match_any(base, value)?;
+            if !base_evaluated.is_number() {
+                return owned(Value::Null);
+            }
+            owned(op.eval_native(&base_evaluated)?)
+        }
+        Node::Compare(base, op) => op.eval_native(value, &*match_any(base, value)?),
+        Node::Arithmetic(base, op) => op.eval_native(value, &*match_any(base, value)?),
+        Node::Pipe(base, then) => match match_any(base, value)? {
+            Cow::Borrowed(base_evaluated) => match_any(then, base_evaluated),
+            Cow::Owned(base_evaluated) => match_any(then, &base_evaluated).map(detached),
+        },
+        Node::MultiList(items) => multi_list(value, items),
+        Node::MultiHash(items) => multi_hash(value, items),
+    }
+}
+
+/// Results of the subexpressions already evaluated against the root document,
+/// as in `matchs::match_shared`.
+pub type Shared<'a> = HashMap<&'a Node, Evaluated<'a>>;
+
+pub fn match_shared<'a>(
+    node: &'a Node,
+    root: &'a Value,
+    shared: &mut Shared<'a>,
+) -> NativeResult<'a> {
+    if let Some(result) = shared.get(node) {
+        return Ok(result.clone());
+    }
+    let result = match node {
+        Node::List(base, op) => match match_shared(base, root, shared)? {
+            Cow::Borrowed(Value::List(list)) => op.eval_native(root, list)?,
+            Cow::Owned(Value::List(list)) => detached(op.eval_native(root, &list)?),
+            _ => Cow::Owned(Value::Null),
+        },
+        Node::Str(base, op) => match match_shared(base, root, shared)?.as_ref() {
+            Value::Str(string) => Cow::Owned(op.eval_native(string)?),
+            _ => Cow::Owned(Value::Null),
+        },
+        Node::Struct(base, op) => match match_shared(base, root, shared)? {
+            Cow::Borrowed(Value::Object(map)) => op.eval_native(map),
+            Cow::Owned(Value::Object(map)) => detached(op.eval_native(&map)),
+            _ => Cow::Owned(Value::Null),
+        },
+        Node::Scalar(base, op) => {
+            let base_evaluated = match_shared(base, root, shared)?;
+            if base_evaluated.is_number() {
+                Cow::Owned(op.eval_native(&base_evaluated)?)
+            } else {
+                Cow::Owned(Value::Null)
+            }
+        }
+        Node::Compare(base, op) => {
+            let left = match_shared(base, root, shared)?;
+            Cow::Owned(op.apply_native(&left, &*match_shared(op.other(), root, shared)?))
+        }
+        Node::Arithmetic(base, op) => {
+            let left = match_shared(base, root, shared)?;
+            Cow::Owned(op.apply_native(&left, &*match_shared(op.other(), root, shared)?)?)
+        }
+        Node::And(a, b) => {
+            let left = match_shared(a, root, shared)?;
+            if left.is_truthy() {
+                match_shared(b, root, shared)?
+            } else {
+                left
+            }
+        }
+        Node::Or(a, b) => {
+            let left = match_shared(a, root, shared)?;
+            if left.is_truthy() {
+                left
+            } else {
+                match_shared(b, root, shared)?
+            }
+        }
+        Node::Not(x) => Cow::Owned(Value::Bool(!match_shared(x, root, shared)?.is_truthy())),
+        Node::Pipe(base, then) => match match_shared(base, root, shared)? {
+            Cow::Borrowed(base_evaluated) => match_any(then, base_evaluated)?,
+            Cow::Owned(base_evaluated) => detached(match_any(then, &base_evaluated)?),
+        },
+        _ => match_any(node, root)?,
+    };
+    shared.insert(node, result.clone());
+    Ok(result)
+}
+
+impl ScalarOp {
+    fn eval_native(&self, number: &Value) -> Result<Value, NativeError> {
+        let float = number.as_f64().unwrap_or_default();
+        Ok(match self {
+            Self::Abs => Value::Float(float.abs()),
+            Self::Ceil => Value::Float(float.ceil()),
+            Self::Floor => Value::Float(float.floor()),
+            Self::Neg => arith::neg(number).ok_or(NativeError::Overflow)?,
+        })
+    }
+}
+
+impl ListOp {
+    fn eval_native<'a>(&'a self, value: &'a Value, list: &'a [Value]) -> NativeResult<'a> {
+        match self {
+            Self::Length => owned(Value::Int(list.len() as i64)),
+            Self::Index(i) => Ok(list::index(list, *i)),
+            Self::Slice { start, end, step } => {
+                let indices = slice_indices(list.len(), start, end, step)?;
+                owned(Value::List(Arc::new(
+                    indices.map(|i| list[i].clone()).collect(),
+                )))
+            }
+            Self::Reverse => owned(Value::List(Arc::new(list.iter().rev().cloned().collect()))),
+            Self::Flatten => owned(list::flatten(list)),
+            Self::Contains(search_node) => {
+                let search = match_any(search_node, value)?;
+                owned(Value::Bool(list.iter().any(|item| py_eq(item, &search))))
+            }
+            Self::Join(glue) => owned(list::join(list, glue)),
+            Self::Filter(cond) => list::filter(list, cond).and_then(owned),
+            Self::Map(key) => list::map(list, key).and_then(owned),
+            Self::FilterMap(cond, key) => list::filter_map(list, cond, key).and_then(owned),
+            Self::Sort => list::sort(list).and_then(owned),
+            Self::Max => list::min_max(list, true),
+            Self::Min => list::min_max(list, false),
+            Self::Sum => owned(list::sum(list)),
+            Self::Avg => owned(list::avg(list)),
+            Self::SortBy(key) => list::sort_like(list, key, list::SortKind::Sort),
+            Self::MinBy(key) => list::sort_like(list, key, list::SortKind::Min),
+            Self::MaxBy(key) => list::sort_like(list, key, list::SortKind::Max),
+            Self::Project(key) => list::project(list, key).and_then(owned),
+        }
+    }
+}
+
+impl StrOp {
+    fn eval_native(&self, string: &str) -> Result<Value, NativeError> {
+        Ok(match self {
+            Self::Slice { start, end, step } => {
+                let chars: Vec<char> = string.chars().collect();
+                let indices = slice_indices(chars.len(), start, end, step)?;
+                Value::Str(Arc::from(indices.map(|i| chars[i]).collect::<String>()))
+            }
+            Self::Reverse => Value::Str(Arc::from(string.chars().rev().collect::<String>())),
+            Self::Contains(search) => Value::Bool(string.contains(search.as_str())),
+            Self::StartsWith(prefix) => Value::Bool(string.starts_with(prefix.as_str())),
+            Self::EndsWith(suffix) => Value::Bool(string.ends_with(suffix.as_str())),
+            Self::Length => Value::Int(string.chars().count() as i64),
+        })
+    }
+}
+
+impl StructOp {
+    fn eval_native<'a>(&self, map: &'a Object) -> Evaluated<'a> {
+        match self {
+            Self::Field(name) => match value::field(map, name) {
+                Some(field) => Cow::Borrowed(field),
+                None => Cow::Owned(Value::Null),
+            },
+            Self::Keys => Cow::Owned(Value::List(Arc::new(
+                map.keys().map(|key| Value::Str(key.clone())).collect(),
+            ))),
+            Self::Values => Cow::Owned(Value::List(Arc::new(map.values().cloned().collect()))),
+        }
+    }
+}
+
+impl ComparisonOp {
+    fn eval_native<'a>(&self, value: &Value, base_evaluated: &Value) -> NativeResult<'a> {
+        let other = match_any(self.other(), value)?;
+        owned(self.apply_native(base_evaluated, &other))
+    }
+
+    fn apply_native(&self, left: &Value, right: &Value) -> Value {
+        let accept: fn(Ordering) -> bool = match self {
+            Self::Eq(_) => return Value::Bool(is_eq(left, right)),
+            Self::Ne(_) => return Value::Bool(!is_eq(left, right)),
+            Self::Lt(_) => Ordering::is_lt,
+            Self::Le(_) => Ordering::is_le,
+            Self::Gt(_) => Ordering::is_gt,
+            Self::Ge(_) => Ordering::is_ge,
+        };
+        Value::Bool(num_cmp(left, right).is_some_and(accept))
+    }
+}
+
+impl ArithmeticOp {
+    fn eval_native<'a>(&self, value: &Value, base_evaluated: &Value) -> NativeResult<'a> {
+        let other = match_any(self.other(), value)?;
+        owned(self.apply_native(base_evaluated, &other)?)
+    }
+
+    fn apply_native(&self, left: &Value, right: &Value) -> Result<Value, NativeError> {
+        if let (Self::Pow(_), Value::Int(base), Value::Int(exponent)) = (self, left, right) {
+            if *exponent >= 0 {
+                let bits = u64::from(i64::BITS - base.unsigned_abs().leading_zeros());
+                eval::check_pow(bits, Some(*exponent as u64))?;
+            }
+        }
+        let op: fn(arith::Operands) -> Option<Value> = match self {
+            Self::Add(_) => arith::add,
+            Self::Sub(_) => arith::sub,
+            Self::Mul(_) => arith::mul,
+            Self::TrueDiv(_) => arith::truediv,
+            Self::FloorDiv(_) => arith::floordiv,
+            Self::Mod(_) => arith::rem,
+            Self::Pow(_) => arith::pow,
+        };
+        arith::Operands::new(left, right)
+            .map_or(Some(Value::Null), op)
+            .ok_or(NativeError::Overflow)
+    }
+}
+
+fn literal(obj: &PyObjectWrapper) -> NativeResult<'_> {
+    obj.1.as_ref().map(Cow::Borrowed).ok_or_else(|| {
+        InvalidArgumentError::new_err(format!(
+            "literal {:?} is not a JSON value and cannot be evaluated on native data",
+            obj
+        ))
+        .into()
+    })
+}
+
+fn coalesce<'a>(value: &'a Value, items: &'a [Node]) -> NativeResult<'a> {
+    for item in items {
+        let evaluated = match_any(item, value)?;
+        if !evaluated.is_null() {
+            return Ok(evaluated);
+        }
+    }
+    owned(Value::Null)
+}
+
+fn merge<'a>(value: &Value, items: &[Node]) -> NativeResult<'a> {
+    let mut output = Object::new();
+    for item in items {
+        match match_any(item, value)?.as_ref() {
+            Value::Object(map) => {
+                output.extend(map.iter().map(|(key, item)| (key.clone(), item.clone())))
+            }
+            _ => return owned(Value::Null),
+        }
+    }
+    owned(Value::Object(Arc::new(output)))
+}
+
+fn multi_list<'a>(value: &Value, items: &[Node]) -> NativeResult<'a> {
+    if value.is_null() {
+        return owned(Value::Null);
+    }
+    owned(Value::List(Arc::new(
+        items
+            .iter()
+            .map(|item| match_any(item, value).map(Cow::into_owned))
+            .collect::<Result<_, NativeError>>()?,
+    )))
+}
+
+fn multi_hash<'a>(value: &Value, items: &[(String, Node)]) -> NativeResult<'a> {
+    if value.is_null() {
+        return owned(Value::Null);
+    }
+    let mut output = Object::with_capacity(items.len());
+    for (key, item) in items {
+        output.insert(
+            Arc::from(key.as_str()),
+            match_any(item, value)?.into_owned(),
+        );
+    }
+    owned(Value::Object(Arc::new(output)))
+}
+
+/// Resolves slice bounds the way Python's `slice.indices` does.
+fn slice_indices(
+    len: usize,
+    start: &Option<isize>,
+    end: &Option<isize>,
+    step: &Option<isize>,
+) -> Result<impl Iterator<Item = usize>, NativeError> {
+    let step = step.unwrap_or(1);
+    if step == 0 {
+        return Err(InvalidArgumentError::new_err("slice step cannot be zero").into());
+    }
+    let len = len as isize;
+    let (lower, upper) = if step < 0 { (-1, len - 1) } else { (0, len) };
+    let clamp = |bound: isize| {
+        if bound < 0 {
+            (bound + len).max(lower)
+        } else {
+            bound.min(upper)
+        }
+    };
+    let start = start.map_or(if step < 0 { upper } else { lower }, clamp);
+    let end = end.map_or(if step < 0 { lower } else { upper }, clamp);
+    let mut current = start;
+    Ok(std::iter::from_fn(move || {
+        let in_range = if step > 0 {
+            current < end
+        } else {
+            current > end
+        };
+        if !in_range {
+            return None;
+        }
+        let index = current as usize;
+        current += step;
+        Some(index)
+    }))
+}
+
+/// Python's `==`, where `True == 1` holds.
+fn py_eq(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Null, Value::Null) => true,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::List(a), Value::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| py_eq(x, y))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, x)| b.get(key).is_some_and(|y| py_eq(x, y)))
+        }
+        _ => match (py_number(left), py_number(right)) {
+            (Some(a), Some(b)) => a.cmp(&b).is_eq(),
+            _ => false,
+        },
+    }
+}
+
+/// Same as `eval::is_eq`: booleans and numbers never compare equal at the top level.
+fn is_eq(left: &Value, right: &Value) -> bool {
+    let mismatched = matches!(left, Value::Bool(_)) && right.is_number()
+        || left.is_number() && matches!(right, Value::Bool(_));
+    !mismatched && py_eq(left, right)
+}
+
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a.cmp(b),
+            (a, b) => a
+                .as_f64()
+                .partial_cmp(&b.as_f64())
+                .unwrap_or(Ordering::Equal),
+        }
+    }
+
+    fn as_f64(&self) -> f64 {
+        match self {
+            Self::Int(i) => *i as f64,
+            Self::Float(f) => *f,
+        }
+    }
+}
+
+fn py_number(value: &Value) -> Option<Number> {
+    match value {
+        Value::Bool(b) => Some(Number::Int(*b as i64)),
+        Value::Int(i) => Some(Number::Int(*i)),
+        Value::Float(f) => Some(Number::Float(*f)),
+        _ => None,
+    }
+}
+
+/// Ordering between two numbers, booleans excluded, as in `eval::cmp_bool`.
+fn num_cmp(left: &Value, right: &Value) -> Option<Ordering> {
+    if !(left.is_number() && right.is_number()) {
+        return None;
+    }
+    Some(py_number(left)?.cmp(&py_number(right)?))
+}
+
+/// Python's `<` ordering as used by `sorted`, raising on unorderable types.
+fn py_cmp(left: &Value, right: &Value) -> Result<Ordering, NativeError> {
+    match (left, right) {
+        (Value::Str(a), Value::Str(b)) => Ok(a.cmp(b)),
+        (Value::List(a), Value::List(b)) => {
+            for (x, y) in a.iter().zip(b.iter()) {
+                if !py_eq(x, y) {
+                    return py_cmp(x, y);
+                }
+            }
+            Ok(a.len().cmp(&b.len()))
+        }
+        _ => match (py_number(left), py_number(right)) {
+            (Some(a), Some(b)) => Ok(a.cmp(&b)),
+            _ => Err(TypeMismatchError::new_err(format!(
+                "'<' not supported between instances of '{}' and '{}'",
+                left.type_name(),
+                right.type_name()
+            ))
+            .into()),
+        },
+    }
+}
+
+pub mod list {
+    use super::*;
+
+    pub enum SortKind {
+        Sort,
+        Min,
+        Max,
+    }
+
+    #[derive(PartialEq)]
+    struct SortKey(Option<f64>);
+
+    impl Eq for SortKey {}
+
+    impl PartialOrd for SortKey {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for SortKey {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+        }
+    }
+
+    pub fn index(list: &[Value], i: isize) -> Evaluated<'_> {
+        let len = list.len() as isize;
+        let idx = if i < 0 { len + i } else { i };
+        if idx < 0 || idx >= len {
+            Cow::Owned(Value::Null)
+        } else {
+            Cow::Borrowed(&list[idx as usize])
+        }
+    }
+
+    pub fn flatten(list: &[Value]) -> Value {
+        let mut output = Vec::with_capacity(list.len());
+        for element in list {
+            match element {
+                Value::List(inner) => output.extend(inner.iter().cloned()),
+                _ => output.push(element.clone()),
+            }
+        }
+        Value::List(Arc::new(output))
+    }
+
+    pub fn filter(list: &[Value], cond: &Node) -> Result<Value, NativeError> {
+        let mut output = Vec::new();
+        for element in list {
+            interrupt::check_detached()?;
+            if match_any(cond, element)?.is_truthy() {
+                output.push(element.clone());
+            }
+        }
+        Ok(Value::List(Arc::new(output)))
+    }
+
+    pub fn map(list: &[Value], key: &Node) -> Result<Value, NativeError> {
+        let mut output = Vec::with_capacity(list.len());
+        for element in list {
+            interrupt::check_detached()?;
+            output.push(match_any(key, element)?.into_owned());
+        }
+        Ok(Value::List(Arc::new(output)))
+    }
+
+    pub fn filter_map(list: &[Value], cond: &Node, key: &Node) -> Result<Value, NativeError> {
+        let mut output = Vec::new();
+        for element in list {
+            interrupt::check_detached()?;
+            if match_any(cond, element)?.is_truthy() {
+                output.push(match_any(key, element)?.into_owned());
+            }
+        }
+        Ok(Value::List(Arc::new(output)))
+    }
+
+    pub fn project(list: &[Value], key: &Node) -> Result<Value, NativeError> {
+        let mut output = Vec::with_capacity(list.len());
+        for element in list {
+            interrupt::check_detached()?;
+            let projected = match_any(key, element)?;
+            if !projected.is_null() {
+                output.push(projected.into_owned());
+            }
+        }
+        Ok(Value::List(Arc::new(output)))
+    }
+
+    /// A filter, map or projection applied to the elements of a list one at a time.
+    pub enum Step {
+        Filter(Node),
+        Map(Node),
+        Project(Node),
+    }
+
+    impl Step {
+        /// Splits a chain of filters, maps and projections over the input list into steps,
+        /// or returns `None` when the expression needs the whole list at once.
+        pub fn chain(node: &Node) -> Option<Vec<Step>> {
+            match node {
+                Node::This => Some(Vec::new()),
+                Node::List(base, op) => {
+                    let mut steps = Self::chain(base)?;
+                    match op {
+                        ListOp::Filter(cond) => steps.push(Self::Filter(cond.as_ref().clone())),
+                        ListOp::Map(key) => steps.push(Self::Map(key.as_ref().clone())),
+                        ListOp::FilterMap(cond, key) => {
+                            steps.push(Self::Filter(cond.as_ref().clone()));
+                            steps.push(Self::Map(key.as_ref().clone()));
+                        }
+                        ListOp::Project(key) => steps.push(Self::Project(key.as_ref().clone())),
+                        _ => return None,
+                    }
+                    Some(steps)
+                }
+                _ => None,
+            }
+        }
+
+        /// Same as `filter`, `map` and `project` on a single element, `None` dropping it.
+        pub fn apply(&self, element: Value) -> Result<Option<Value>, NativeError> {
+            interrupt::check_detached()?;
+            match self {
+                Self::Filter(cond) => Ok(match_any(cond, &element)?.is_truthy().then_some(element)),
+                Self::Map(key) => Ok(Some(match_any(key, &element)?.into_owned())),
+                Self::Project(key) => {
+                    let projected = match_any(key, &element)?.into_owned();
+                    Ok((!projected.is_null()).then_some(projected))
+                }
+            }
+        }
+    }
+
+    pub fn sort(list: &[Value]) -> Result<Value, NativeError> {
+        let mut output = list.to_vec();
+        let mut error = None;
+        output.sort_by(|a, b| {
+            py_cmp(a, b).unwrap_or_else(|err| {
+                error.get_or_insert(err);
+                Ordering::Equal
+            })
+        });
+        match error {
+            Some(err) => Err(err),
+            None => Ok(Value::List(Arc::new(output))),
+        }
+    }
+
+    pub fn sort_like<'a>(list: &'a [Value], key: &Node, kind: SortKind) -> NativeResult<'a> {
+        type SortedVec<'a> = Vec<(u8, SortKey, Option<i64>, Option<Arc<str>>, &'a Value)>;
+        let mut pairs: SortedVec = Vec::with_capacity(list.len());
+
+        for element in list {
+            interrupt::check_detached()?;
+            let (f, i, s) = match match_any(key, element)?.as_ref() {
+                Value::Bool(b) => (Some(*b as i64 as f64), Some(*b as i64), None),
+                Value::Int(i) => (Some(*i as f64), Some(*i), None),
+                Value::Float(f) => (Some(*f), None, None),
+                Value::Str(s) => (None, None, Some(s.clone())),
+                _ => (None, None, None),
+            };
+            let has = if f.is_some() || i.is_some() || s.is_some() {
+                0
+            } else {
+                1
+            };
+            pairs.push((has, SortKey(f), i, s, element));
+        }
+
+        let order = |a: &(u8, SortKey, Option<i64>, Option<Arc<str>>, &Value),
+                     b: &(u8, SortKey, Option<i64>, Option<Arc<str>>, &Value)| {
+            (a.0, &a.1, a.2, a.3.as_deref()).cmp(&(b.0, &b.1, b.2, b.3.as_deref()))
+        };
+        match kind {
+            SortKind::Sort => {
+                pairs.sort_by(order);
+                owned(Value::List(Arc::new(
+                    pairs.into_iter().map(|pair| pair.4.clone()).collect(),
+                )))
+            }
+            SortKind::Min => Ok(pairs
+                .iter()
+                .min_by(|a, b| order(a, b))
+                .map_or(Cow::Owned(Value::Null), |min| Cow::Borrowed(min.4))),
+            SortKind::Max => Ok(pairs
+                .iter()
+                .max_by(|a, b| order(a, b))
+                .map_or(Cow::Owned(Value::Null), |max| Cow::Borrowed(max.4))),
+        }
+    }
+
+    pub fn sum(list: &[Value]) -> Value {
+        let mut sum = 0.0;
+        for element in list {
+            match element.as_f64() {
+                Some(number) => sum += number,
+                None => return Value::Null,
+            }
+        }
+        Value::Float(sum)
+    }
+
+    pub fn avg(list: &[Value]) -> Value {
+        if list.is_empty() {
+            return Value::Null;
+        }
+        match sum(list) {
+            Value::Float(sum) => Value::Float(sum / list.len() as f64),
+            _ => Value::Null,
+        }
+    }
+
+    pub fn min_max(list: &[Value], is_max: bool) -> NativeResult<'_> {
+        let Some((first, rest)) = list.split_first() else {
+            return owned(Value::Null);
+        };
+        let expect_number = first.is_number();
+        let expect_string = matches!(first, Value::Str(_));
+        if !expect_number && !expect_string {
+            return owned(Value::Null);
+        }
+        let wanted = if is_max {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        };
+        let mut best = first;
+        for current in rest {
+            if expect_number != current.is_number()
+                || expect_string != matches!(current, Value::Str(_))
+            {
+                return owned(Value::Null);
+            }
+            if py_cmp(current, best)? == wanted {
+                best = current;
+            }
+        }
+        Ok(Cow::Borrowed(best))
+    }
+
+    pub fn join(list: &[Value], glue: &str) -> Value {
+        let mut parts = Vec::with_capacity(list.len());
+        for element in list {
+            match element {
+                Value::Str(s) => parts.push(s.as_ref()),
+                _ => return Value::Null,
+            }
+        }
+        Value::Str(Arc::from(parts.join(glue)))
+    }
+}
+
+/// Each operation gives `None` when its integer result does not fit in 64 bits,
+/// rather than a float that would differ from the exact Python integer.
+pub mod arith {
+    use super::*;
+
+    /// Two numeric operands, booleans excluded, as in `eval::arithmetic`.
+    pub enum Operands {
+        Ints(i64, i64),
+        Floats(f64, f64),
+    }
+
+    impl Operands {
+        pub fn new(left: &Value, right: &Value) -> Option<Self> {
+            match (left, right) {
+                (Value::Int(a), Value::Int(b)) => Some(Self::Ints(*a, *b)),
+                _ => Some(Self::Floats(left.as_f64()?, right.as_f64()?)),
+            }
+        }
+    }
+
+    fn float(result: f64) -> Value {
+        Value::Float(result)
+    }
+
+    pub fn neg(number: &Value) -> Option<Value> {
+        match number {
+            Value::Int(i) => i.checked_neg().map(Value::Int),
+            Value::Float(f) => Some(float(-f)),
+            _ => Some(Value::Null),
+        }
+    }
+
+    pub fn add(operands: Operands) -> Option<Value> {
+        match operands {
+            Operands::Ints(a, b) => a.checked_add(b).map(Value::Int),
+            Operands::Floats(a, b) => Some(float(a + b)),
+        }
+    }
+
+    pub fn sub(operands: Operands) -> Option<Value> {
+        match operands {
+            Operands::Ints(a, b) => a.checked_sub(b).map(Value::Int),
+            Operands::Floats(a, b) => Some(float(a - b)),
+        }
+    }
+
+    pub fn mul(operands: Operands) -> Option<Value> {
+        match operands {
+            Operands::Ints(a, b) => a.checked_mul(b).map(Value::Int),
+            Operands::Floats(a, b) => Some(float(a * b)),
+        }
+    }
+
+    pub fn truediv(operands: Operands) -> Option<Value> {
+        let (a, b) = match operands {
+            Operands::Ints(a, b) => (a as f64, b as f64),
+            Operands::Floats(a, b) => (a, b),
+        };
+        if b == 0.0 {
+            return Some(Value::Null);
+        }
+        Some(float(a / b))
+    }
+
+    /// Python's `divmod` on floats, where the remainder takes the sign of the divisor.
+    fn float_divmod(a: f64, b: f64) -> (f64, f64) {
+        let mut rem = a % b;
+        let mut div = (a - rem) / b;
+        if rem != 0.0 {
+            if (b < 0.0) != (rem < 0.0) {
+                rem += b;
+                div -= 1.0;
+            }
+        } else {
+            rem = 0.0_f64.copysign(b);
+        }
+        let floor = if div != 0.0 {
+            let floor = div.floor();
+            if div - floor > 0.5 {
+                floor + 1.0
+            } else {
+                floor
+            }
+        } else {
+            0.0_f64.copysign(a / b)
+        };
+        (floor, rem)
+    }
+
+    pub fn floordiv(operands: Operands) -> Option<Value> {
+        match operands {
+            Operands::Ints(_, 0) => Some(Value::Null),
+            Operands::Ints(a, b) => a.checked_div(b).map(|quotient| {
+                let adjust = a % b != 0 && (a % b < 0) != (b < 0);
+                Value::Int(quotient - adjust as i64)
+            }),
+            Operands::Floats(_, 0.0) => Some(Value::Null),
+            Operands::Floats(a, b) => Some(float(float_divmod(a, b).0)),
+        }
+    }
+
+    pub fn rem(operands: Operands) -> Option<Value> {
+        Some(match operands {
+            Operands::Ints(_, 0) => Value::Null,
+            Operands::Ints(a, b) => match a.checked_rem(b) {
+                Some(remainder) if remainder != 0 && (remainder < 0) != (b < 0) => {
+                    Value::Int(remainder + b)
+                }
+                Some(remainder) => Value::Int(remainder),
+                None => Value::Int(0),
+            },
+            Operands::Floats(_, 0.0) => Value::Null,
+            Operands::Floats(a, b) => float(float_divmod(a, b).1),
+        })
+    }
+
+    pub fn pow(operands: Operands) -> Option<Value> {
+        let (a, b) = match operands {
+            Operands::Ints(a, b) if b >= 0 => {
+                let exponent = u32::try_from(b).unwrap_or(u32::MAX - (b % 2 == 0) as u32);
+                return a.checked_pow(exponent).map(Value::Int);
+            }
+            Operands::Ints(a, b) => (a as f64, b as f64),
+            Operands::Floats(a, b) => (a, b),
+        };
+        if a == 0.0 && b < 0.0 || a < 0.0 && b.fract() != 0.0 {
+            return Some(Value::Null);
+        }
+        let result = a.powf(b);
+        if result.is_infinite() && a.is_finite() && b.is_finite() {
+            return Some(Value::Null);
+        }
+        Some(float(result))
+    }
+}
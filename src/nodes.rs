@@ -3,50 +3,101 @@ use pyo3::{
     types::{PyDict, PyString, PyTuple},
 };
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 use crate::eval;
+use crate::value::{self, Value};
 
 pub type EvalResult<'py> = PyResult<Bound<'py, PyAny>>;
 pub type Bounded<'py> = Bound<'py, PyAny>;
 
-pub(crate) struct PyObjectWrapper(pub Py<PyAny>);
+/// A literal, along with its native counterpart when it is JSON-compatible.
+///
+/// A JSON-compatible literal holds a copy rebuilt from its native counterpart rather than
+/// the object it was given, so that changing that object afterwards changes neither
+/// evaluator's view of the literal.
+pub(crate) struct PyObjectWrapper(pub Py<PyAny>, pub Option<Value>);
 
 impl Clone for PyObjectWrapper {
     fn clone(&self) -> Self {
-        Python::attach(|py| Self(self.0.clone_ref(py)))
+        Python::attach(|py| Self(self.0.clone_ref(py), self.1.clone()))
+    }
+}
+
+impl PyObjectWrapper {
+    pub fn new(obj: &Bound<'_, PyAny>) -> Self {
+        value::from_py(obj)
+            .and_then(|native| Ok(Self(native.to_py(obj.py())?.unbind(), Some(native))))
+            .unwrap_or_else(|_| Self(obj.clone().unbind(), None))
+    }
+
+    pub fn to_json(&self, py: Python<'_>) -> PyResult<String> {
+        let json = py.import(eval::pylibs::JSON)?;
+        let kwargs = PyDict::new(py);
+        let seps = PyTuple::new(
+            py,
+            &[
+                PyString::new(py, ",").into_any(),
+                PyString::new(py, ":").into_any(),
+            ],
+        )?;
+        kwargs.set_item("separators", seps)?;
+        json.getattr("dumps")?
+            .call((self.0.bind(py),), Some(&kwargs))?
+            .extract::<String>()
+    }
+
+    /// The JSON text of the literal, or its `repr` when it is not JSON-compatible.
+    pub fn describe(&self, py: Python<'_>) -> String {
+        self.to_json(py)
+            .unwrap_or_else(|_| match self.0.bind(py).repr() {
+                Ok(repr) => repr.to_string(),
+                Err(_) => "PyObject(repr_failed)".to_string(),
+            })
+    }
+}
+
+/// Literals are the same when their native values are, and otherwise when they are Python
+/// objects of the same type comparing equal.
+impl PartialEq for PyObjectWrapper {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.1, &other.1) {
+            (Some(left), Some(right)) => left == right,
+            (None, None) => Python::attach(|py| {
+                let (left, right) = (self.0.bind(py), other.0.bind(py));
+                left.is(right)
+                    || left.get_type().is(right.get_type()) && left.eq(right).unwrap_or(false)
+            }),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for PyObjectWrapper {}
+
+/// Python objects are hashed with `hash()`, or by type when they are not hashable.
+impl Hash for PyObjectWrapper {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.1 {
+            Some(value) => value.hash(state),
+            None => Python::attach(|py| {
+                let obj = self.0.bind(py);
+                match obj.hash() {
+                    Ok(hash) => hash.hash(state),
+                    Err(_) => obj.get_type().as_ptr().hash(state),
+                }
+            }),
+        }
     }
 }
 
 impl fmt::Debug for PyObjectWrapper {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        Python::attach(|py| {
-            let obj = self.0.bind(py);
-            let json_result: Result<Bound<PyAny>, PyErr> = (|| {
-                let json = py.import(eval::pylibs::JSON)?;
-                let kwargs = PyDict::new(py);
-                let seps = PyTuple::new(
-                    py,
-                    &[
-                        PyString::new(py, ",").into_any(),
-                        PyString::new(py, ":").into_any(),
-                    ],
-                )?;
-                kwargs.set_item("separators", seps)?;
-                json.getattr("dumps")?.call((obj,), Some(&kwargs))
-            })();
-
-            match json_result {
-                Ok(json_string) => write!(f, "`{}`", json_string),
-                Err(_) => match obj.repr() {
-                    Ok(repr) => write!(f, "`{}`", repr),
-                    Err(_) => write!(f, "PyObject(repr_failed)"),
-                },
-            }
-        })
+        Python::attach(|py| write!(f, "`{}`", self.describe(py)))
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum Node {
     This,
     Literal(PyObjectWrapper),
@@ -60,9 +111,13 @@ pub(crate) enum Node {
     Struct(Box<Node>, StructOp),
     Scalar(Box<Node>, ScalarOp),
     Compare(Box<Node>, ComparisonOp),
+    Arithmetic(Box<Node>, ArithmeticOp),
+    Pipe(Box<Node>, Box<Node>),
+    MultiList(Vec<Node>),
+    MultiHash(Vec<(String, Node)>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum ListOp {
     Index(isize),
     Slice {
@@ -76,6 +131,8 @@ pub(crate) enum ListOp {
     Contains(Box<Node>),
     Filter(Box<Node>),
     Map(Box<Node>),
+    /// A `Filter` followed by a `Map`, fused by the optimizer into a single pass.
+    FilterMap(Box<Node>, Box<Node>),
     Join(String),
     Sort,
     Max,
@@ -85,9 +142,10 @@ pub(crate) enum ListOp {
     SortBy(Box<Node>),
     MinBy(Box<Node>),
     MaxBy(Box<Node>),
+    Project(Box<Node>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum StrOp {
     Slice {
         start: Option<isize>,
@@ -101,21 +159,22 @@ pub(crate) enum StrOp {
     Length,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum StructOp {
     Field(String),
     Keys,
     Values,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum ScalarOp {
     Abs,
     Ceil,
     Floor,
+    Neg,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum ComparisonOp {
     Eq(Box<Node>),
     Ne(Box<Node>),
@@ -124,3 +183,163 @@ pub(crate) enum ComparisonOp {
     Gt(Box<Node>),
     Ge(Box<Node>),
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum ArithmeticOp {
+    Add(Box<Node>),
+    Sub(Box<Node>),
+    Mul(Box<Node>),
+    TrueDiv(Box<Node>),
+    FloorDiv(Box<Node>),
+    Mod(Box<Node>),
+    Pow(Box<Node>),
+}
+
+impl ComparisonOp {
+    /// The right-hand side, evaluated against the same input as the base.
+    pub fn other(&self) -> &Node {
+        match self {
+            Self::Eq(other)
+            | Self::Ne(other)
+            | Self::Lt(other)
+            | Self::Le(other)
+            | Self::Gt(other)
+            | Self::Ge(other) => other,
+        }
+    }
+
+    pub fn other_mut(&mut self) -> &mut Node {
+        match self {
+            Self::Eq(other)
+            | Self::Ne(other)
+            | Self::Lt(other)
+            | Self::Le(other)
+            | Self::Gt(other)
+            | Self::Ge(other) => other,
+        }
+    }
+}
+
+impl ArithmeticOp {
+    pub fn other(&self) -> &Node {
+        match self {
+            Self::Add(other)
+            | Self::Sub(other)
+            | Self::Mul(other)
+            | Self::TrueDiv(other)
+            | Self::FloorDiv(other)
+            | Self::Mod(other)
+            | Self::Pow(other) => other,
+        }
+    }
+
+    pub fn other_mut(&mut self) -> &mut Node {
+        match self {
+            Self::Add(other)
+            | Self::Sub(other)
+            | Self::Mul(other)
+            | Self::TrueDiv(other)
+            | Self::FloorDiv(other)
+            | Self::Mod(other)
+            | Self::Pow(other) => other,
+        }
+    }
+}
+
+impl Node {
+    /// Visits the direct subexpressions, whatever input they are evaluated against.
+    pub fn for_each_child<'a>(&'a self, f: &mut impl FnMut(&'a Node)) {
+        match self {
+            Self::This | Self::Literal(_) => {}
+            Self::And(a, b) | Self::Or(a, b) | Self::Pipe(a, b) => {
+                f(a);
+                f(b);
+            }
+            Self::Not(x) => f(x),
+            Self::Coalesce(items) | Self::Merge(items) | Self::MultiList(items) => {
+                items.iter().for_each(f)
+            }
+            Self::MultiHash(items) => items.iter().for_each(|(_, item)| f(item)),
+            Self::List(base, op) => {
+                f(base);
+                match op {
+                    ListOp::FilterMap(cond, key) => {
+                        f(cond);
+                        f(key);
+                    }
+                    ListOp::Contains(arg)
+                    | ListOp::Filter(arg)
+                    | ListOp::Map(arg)
+                    | ListOp::SortBy(arg)
+                    | ListOp::MinBy(arg)
+                    | ListOp::MaxBy(arg)
+                    | ListOp::Project(arg) => f(arg),
+                    _ => {}
+                }
+            }
+            Self::Str(base, _) | Self::Struct(base, _) | Self::Scalar(base, _) => f(base),
+            Self::Compare(base, op) => {
+                f(base);
+                f(op.other());
+            }
+            Self::Arithmetic(base, op) => {
+                f(base);
+                f(op.other());
+            }
+        }
+    }
+
+    pub fn for_each_child_mut(&mut self, f: &mut impl FnMut(&mut Node)) {
+        match self {
+            Self::This | Self::Literal(_) => {}
+            Self::And(a, b) | Self::Or(a, b) | Self::Pipe(a, b) => {
+                f(a);
+                f(b);
+            }
+            Self::Not(x) => f(x),
+            Self::Coalesce(items) | Self::Merge(items) | Self::MultiList(items) => {
+                items.iter_mut().for_each(f)
+            }
+            Self::MultiHash(items) => items.iter_mut().for_each(|(_, item)| f(item)),
+            Self::List(base, op) => {
+                f(base);
+                match op {
+                    ListOp::FilterMap(cond, key) => {
+                        f(cond);
+                        f(key);
+                    }
+                    ListOp::Contains(arg)
+                    | ListOp::Filter(arg)
+                    | ListOp::Map(arg)
+                    | ListOp::SortBy(arg)
+                    | ListOp::MinBy(arg)
+                    | ListOp::MaxBy(arg)
+                    | ListOp::Project(arg) => f(arg),
+                    _ => {}
+                }
+            }
+            Self::Str(base, _) | Self::Struct(base, _) | Self::Scalar(base, _) => f(base),
+            Self::Compare(base, op) => {
+                f(base);
+                f(op.other_mut());
+            }
+            Self::Arithmetic(base, op) => {
+                f(base);
+                f(op.other_mut());
+            }
+        }
+    }
+
+    /// Whether every literal has a native counterpart, so that the node can be evaluated
+    /// on native documents.
+    pub fn is_native(&self) -> bool {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if matches!(node, Self::Literal(obj) if obj.1.is_none()) {
+                return false;
+            }
+            node.for_each_child(&mut |child| stack.push(child));
+        }
+        true
+    }
+}
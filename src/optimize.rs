@@ -0,0 +1,197 @@
+use crate::errors::TypeMismatchError;
+use crate::limits;
+use crate::matchs::match_any;
+use crate::nodes::{ArithmeticOp, ListOp, Node, PyObjectWrapper};
+use crate::value::Value;
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+/// Rewrites an expression into an equivalent one that is cheaper to evaluate.
+///
+/// Every rewrite keeps the result identical on any input, in both evaluation modes,
+/// although type mismatches between literals are folded to the `None` they give outside
+/// of strict mode, which is why strict and traced evaluations run the expression as written.
+///
+/// Since the rewrites recurse over the expression, its depth is checked first.
+pub fn optimize(py: Python<'_>, node: Node) -> PyResult<Node> {
+    limits::check_expr(&node)?;
+    rewrite(py, node)
+}
+
+fn rewrite(py: Python<'_>, mut node: Node) -> PyResult<Node> {
+    let mut result = Ok(());
+    node.for_each_child_mut(&mut |child| {
+        if result.is_ok() {
+            match rewrite(py, std::mem::replace(child, Node::This)) {
+                Ok(rewritten) => *child = rewritten,
+                Err(err) => result = Err(err),
+            }
+        }
+    });
+    result?;
+    simplify(py, node)
+}
+
+fn simplify(py: Python<'_>, node: Node) -> PyResult<Node> {
+    if let Some(folded) = fold(py, &node)? {
+        return Ok(folded);
+    }
+    Ok(match node {
+        Node::Not(x) => match *x {
+            Node::Not(inner) if is_boolean(&inner) => *inner,
+            x => Node::Not(x.into()),
+        },
+        // Only exact booleans can be dropped: `a & 5` gives `5`, not `a`, when `a` is true.
+        Node::And(a, b) => match (truthiness(&a), literal(&b)) {
+            (Some(true), _) => *b,
+            (Some(false), _) => *a,
+            (None, Some(Value::Bool(true))) if is_boolean(&a) => *a,
+            _ => Node::And(a, b),
+        },
+        Node::Or(a, b) => match (truthiness(&a), literal(&b)) {
+            (Some(true), _) => *a,
+            (Some(false), _) => *b,
+            (None, Some(Value::Bool(false))) if is_boolean(&a) => *a,
+            _ => Node::Or(a, b),
+        },
+        Node::Coalesce(items) => coalesce(py, items),
+        Node::Pipe(base, then) => match (*base, *then) {
+            (Node::This, then) => then,
+            (base, Node::This) => base,
+            (base, then) => Node::Pipe(base.into(), then.into()),
+        },
+        Node::List(base, ListOp::Map(key)) => match *base {
+            Node::List(inner, ListOp::Map(first)) => {
+                Node::List(inner, ListOp::Map(pipe(py, first, key)?))
+            }
+            Node::List(inner, ListOp::Filter(cond)) => {
+                Node::List(inner, ListOp::FilterMap(cond, key))
+            }
+            Node::List(inner, ListOp::FilterMap(cond, first)) => {
+                Node::List(inner, ListOp::FilterMap(cond, pipe(py, first, key)?))
+            }
+            base => Node::List(base.into(), ListOp::Map(key)),
+        },
+        node => node,
+    })
+}
+
+/// Mapping `first` then `then` over a list is mapping `first | then`.
+fn pipe(py: Python<'_>, first: Box<Node>, then: Box<Node>) -> PyResult<Box<Node>> {
+    Ok(simplify(py, Node::Pipe(first, then))?.into())
+}
+
+/// Null literals never win a coalesce, and nothing after a non-null literal is reached.
+fn coalesce(py: Python<'_>, items: Vec<Node>) -> Node {
+    let mut kept = Vec::with_capacity(items.len());
+    for item in items {
+        match literal(&item) {
+            Some(Value::Null) => continue,
+            Some(_) => {
+                kept.push(item);
+                break;
+            }
+            None => kept.push(item),
+        }
+    }
+    match kept.len() {
+        0 => Node::Literal(PyObjectWrapper::new(&py.None().into_bound(py))),
+        1 => kept.remove(0),
+        _ => Node::Coalesce(kept),
+    }
+}
+
+/// Evaluates the operations that take constant time, once their operands were folded into
+/// scalar literals: comparisons, arithmetic other than `**`, scalar functions and `not`.
+///
+/// Operations over lists, dicts or strings are left to the evaluation, whose cost is bounded
+/// by the limits of `collect`, and the result is only folded when the native evaluator would
+/// agree with the Python one, which integers beyond 64 bits would not.
+fn fold(py: Python<'_>, node: &Node) -> PyResult<Option<Node>> {
+    let is_foldable = match node {
+        Node::Not(x) | Node::Scalar(x, _) => is_scalar(x),
+        Node::Compare(base, op) => is_scalar(base) && is_scalar(op.other()),
+        Node::Arithmetic(_, ArithmeticOp::Pow(_)) => false,
+        Node::Arithmetic(base, op) => is_scalar(base) && is_scalar(op.other()),
+        _ => false,
+    };
+    if !is_foldable {
+        return Ok(None);
+    }
+    let result = match match_any(py, node, &py.None().into_bound(py)) {
+        Ok(result) => result,
+        Err(err) if err.is_instance_of::<TypeMismatchError>(py) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let literal = PyObjectWrapper::new(&result);
+    Ok(literal.1.is_some().then_some(Node::Literal(literal)))
+}
+
+fn is_scalar(node: &Node) -> bool {
+    matches!(
+        literal(node),
+        Some(Value::Null | Value::Bool(_) | Value::Int(_) | Value::Float(_) | Value::Str(_))
+    )
+}
+
+fn literal(node: &Node) -> Option<&Value> {
+    match node {
+        Node::Literal(obj) => obj.1.as_ref(),
+        _ => None,
+    }
+}
+
+fn truthiness(node: &Node) -> Option<bool> {
+    literal(node).map(Value::is_truthy)
+}
+
+/// Whether the expression always evaluates to `True` or `False`.
+fn is_boolean(node: &Node) -> bool {
+    match node {
+        Node::Not(_) | Node::Compare(..) => true,
+        _ => matches!(literal(node), Some(Value::Bool(_))),
+    }
+}
+
+/// Whether a subexpression evaluated against the root document appears more than once,
+/// in which case evaluating it once with `match_shared` pays off.
+pub fn has_common_subexpressions(node: &Node) -> bool {
+    // A subexpression cannot repeat along a single chain of bases, since each one is
+    // strictly contained in the next: only binary nodes can hold two identical branches.
+    fn branches(node: &Node) -> bool {
+        match root_children(node) {
+            [Some(_), Some(_)] => true,
+            [Some(child), None] => branches(child),
+            _ => false,
+        }
+    }
+    fn visit<'n>(node: &'n Node, seen: &mut HashMap<&'n Node, usize>) -> bool {
+        if matches!(node, Node::This | Node::Literal(_)) {
+            return false;
+        }
+        let count = seen.entry(node).or_default();
+        *count += 1;
+        *count > 1
+            || root_children(node)
+                .into_iter()
+                .flatten()
+                .any(|child| visit(child, seen))
+    }
+    branches(node) && visit(node, &mut HashMap::new())
+}
+
+/// The subexpressions `match_shared` evaluates against the same input as `node`.
+fn root_children(node: &Node) -> [Option<&Node>; 2] {
+    match node {
+        Node::List(base, _)
+        | Node::Str(base, _)
+        | Node::Struct(base, _)
+        | Node::Scalar(base, _)
+        | Node::Pipe(base, _)
+        | Node::Not(base) => [Some(base), None],
+        Node::Compare(base, op) => [Some(base), Some(op.other())],
+        Node::Arithmetic(base, op) => [Some(base), Some(op.other())],
+        Node::And(a, b) | Node::Or(a, b) => [Some(a), Some(b)],
+        _ => [None, None],
+    }
+}
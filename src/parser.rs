@@ -0,0 +1,686 @@
+use crate::errors::ParseError;
+use crate::eval::pylibs;
+use crate::lexer::{self, SyntaxError, Token, TokenKind};
+use crate::limits::{self, MAX_EXPR_DEPTH};
+use crate::nodes::{ComparisonOp, ListOp, Node, PyObjectWrapper, ScalarOp, StrOp, StructOp};
+use pyo3::prelude::*;
+use pyo3::types::PyString;
+
+const PROJECTION_STOP: u8 = 10;
+
+type ParseResult<T> = Result<T, SyntaxError>;
+
+/// Queries nested deeper than `MAX_EXPR_DEPTH` are refused like the expressions built in
+/// Python, whether the parser or the tree it built goes that deep.
+pub fn parse(py: Python<'_>, text: &str) -> PyResult<Node> {
+    let tokens = lexer::tokenize(text).map_err(|err| into_pyerr(py, text, err))?;
+    let mut parser = Parser {
+        py,
+        tokens,
+        pos: 0,
+        depth: 0,
+        too_deep: false,
+    };
+    let node = match parser.run() {
+        Ok(node) => node,
+        Err(_) if parser.too_deep => return Err(limits::expr_too_deep()),
+        Err(err) => return Err(into_pyerr(py, text, err)),
+    };
+    limits::check_expr(&node)?;
+    Ok(node)
+}
+
+fn into_pyerr(py: Python<'_>, text: &str, err: SyntaxError) -> PyErr {
+    let before = &text[..err.offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    let py_err = ParseError::new_err(format!(
+        "{} at line {}, column {}",
+        err.message, line, column
+    ));
+    let value = py_err.value(py);
+    match value
+        .setattr("line", line)
+        .and_then(|_| value.setattr("column", column))
+        .and_then(|_| value.setattr("expr", text))
+    {
+        Ok(()) => py_err,
+        Err(setattr_err) => setattr_err,
+    }
+}
+
+fn field(base: Node, name: String) -> Node {
+    Node::Struct(base.into(), StructOp::Field(name))
+}
+
+fn project(base: Node, rhs: Node) -> Node {
+    Node::List(base.into(), ListOp::Project(rhs.into()))
+}
+
+/// A node is a chain when it only reads the current value through its innermost base.
+fn is_chain(node: &Node) -> bool {
+    match node {
+        Node::This => true,
+        Node::Struct(base, _) | Node::Str(base, _) | Node::Scalar(base, _) => is_chain(base),
+        Node::List(base, op) => !matches!(op, ListOp::Contains(_)) && is_chain(base),
+        Node::Pipe(base, _) => is_chain(base),
+        _ => false,
+    }
+}
+
+fn rebase(node: Node, base: Node) -> Node {
+    match node {
+        Node::This => base,
+        Node::Struct(inner, op) => Node::Struct(rebase(*inner, base).into(), op),
+        Node::Str(inner, op) => Node::Str(rebase(*inner, base).into(), op),
+        Node::Scalar(inner, op) => Node::Scalar(rebase(*inner, base).into(), op),
+        Node::List(inner, op) => Node::List(rebase(*inner, base).into(), op),
+        Node::Pipe(inner, then) => Node::Pipe(rebase(*inner, base).into(), then),
+        other => Node::Pipe(base.into(), other.into()),
+    }
+}
+
+/// Evaluates `rhs` against the result of `base`, inlining `base` when `rhs` is a chain.
+fn pipe(base: Node, rhs: Node) -> Node {
+    match base {
+        Node::This => rhs,
+        base if is_chain(&rhs) => rebase(rhs, base),
+        base => Node::Pipe(base.into(), rhs.into()),
+    }
+}
+
+struct Arg {
+    node: Node,
+    is_ref: bool,
+    offset: usize,
+}
+
+struct Call<'a> {
+    name: &'a str,
+    args: Vec<Arg>,
+    offset: usize,
+}
+
+impl Call<'_> {
+    fn exact<const N: usize>(self) -> ParseResult<[Arg; N]> {
+        let found = self.args.len();
+        self.args.try_into().map_err(|_| {
+            SyntaxError::new(
+                format!(
+                    "function '{}' expects {} argument(s), found {}",
+                    self.name, N, found
+                ),
+                self.offset,
+            )
+        })
+    }
+
+    fn unary(self) -> ParseResult<Node> {
+        let name = self.name;
+        let [arg] = self.exact::<1>()?;
+        expr(name, arg)
+    }
+
+    fn variadic(self, min: usize) -> ParseResult<Vec<Node>> {
+        if self.args.len() < min {
+            return Err(SyntaxError::new(
+                format!(
+                    "function '{}' expects at least {} argument(s), found {}",
+                    self.name,
+                    min,
+                    self.args.len()
+                ),
+                self.offset,
+            ));
+        }
+        let name = self.name;
+        self.args.into_iter().map(|arg| expr(name, arg)).collect()
+    }
+}
+
+fn expr(function: &str, arg: Arg) -> ParseResult<Node> {
+    if arg.is_ref {
+        return Err(SyntaxError::new(
+            format!(
+                "function '{}' does not accept an expression reference here",
+                function
+            ),
+            arg.offset,
+        ));
+    }
+    Ok(arg.node)
+}
+
+fn expref(function: &str, arg: Arg) -> ParseResult<Node> {
+    if !arg.is_ref {
+        return Err(SyntaxError::new(
+            format!(
+                "function '{}' expects an expression reference ('&')",
+                function
+            ),
+            arg.offset,
+        ));
+    }
+    Ok(arg.node)
+}
+
+struct Parser<'py> {
+    py: Python<'py>,
+    tokens: Vec<Token>,
+    pos: usize,
+    /// How many expressions are being parsed around the current one.
+    depth: usize,
+    /// Whether parsing stopped because `depth` reached `MAX_EXPR_DEPTH`.
+    too_deep: bool,
+}
+
+impl Parser<'_> {
+    fn run(&mut self) -> ParseResult<Node> {
+        let node = self.expression(0)?;
+        match self.current() {
+            TokenKind::Eof => Ok(node),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn current(&self) -> &TokenKind {
+        &self.tokens[self.pos].kind
+    }
+
+    fn lookahead(&self, ahead: usize) -> &TokenKind {
+        self.tokens
+            .get(self.pos + ahead)
+            .map(|token| &token.kind)
+            .unwrap_or(&TokenKind::Eof)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens[self.pos].offset
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> ParseResult<()> {
+        if *self.current() == kind {
+            self.advance();
+            Ok(())
+        } else {
+            Err(SyntaxError::new(
+                format!("expected {}, found {}", kind, self.current()),
+                self.offset(),
+            ))
+        }
+    }
+
+    fn unexpected(&self) -> SyntaxError {
+        SyntaxError::new(format!("unexpected {}", self.current()), self.offset())
+    }
+
+    fn expression(&mut self, binding_power: u8) -> ParseResult<Node> {
+        if self.depth == MAX_EXPR_DEPTH {
+            self.too_deep = true;
+            return Err(SyntaxError::new(
+                "expression is nested too deep",
+                self.offset(),
+            ));
+        }
+        self.depth += 1;
+        let parsed = self.pratt(binding_power);
+        self.depth -= 1;
+        parsed
+    }
+
+    /// Parses an expression whose operators bind tighter than `binding_power`.
+    fn pratt(&mut self, binding_power: u8) -> ParseResult<Node> {
+        let token = self.advance();
+        let mut left = self.nud(token)?;
+        while binding_power < self.current().binding_power() {
+            let token = self.advance();
+            left = self.led(token, left)?;
+        }
+        Ok(left)
+    }
+
+    fn nud(&mut self, token: Token) -> ParseResult<Node> {
+        match token.kind {
+            TokenKind::Literal(raw) => self.json_literal(&raw, token.offset),
+            TokenKind::RawString(raw) => Ok(Node::Literal(PyObjectWrapper::new(
+                PyString::new(self.py, &raw).as_any(),
+            ))),
+            TokenKind::Identifier(name) => Ok(field(Node::This, name)),
+            TokenKind::QuotedIdentifier(name) => {
+                if *self.current() == TokenKind::LParen {
+                    return Err(SyntaxError::new(
+                        "quoted identifiers cannot be used as function names",
+                        token.offset,
+                    ));
+                }
+                Ok(field(Node::This, name))
+            }
+            TokenKind::Current => Ok(Node::This),
+            TokenKind::Star => {
+                let rhs = if *self.current() == TokenKind::RBracket {
+                    Node::This
+                } else {
+                    self.projection_rhs(TokenKind::Star.binding_power())?
+                };
+                Ok(project(
+                    Node::Struct(Node::This.into(), StructOp::Values),
+                    rhs,
+                ))
+            }
+            TokenKind::Flatten => {
+                let rhs = self.projection_rhs(TokenKind::Flatten.binding_power())?;
+                Ok(project(Node::List(Node::This.into(), ListOp::Flatten), rhs))
+            }
+            TokenKind::Filter => self.filter_projection(Node::This),
+            TokenKind::LBrace => self.multi_hash(),
+            TokenKind::Not => Ok(Node::Not(
+                self.expression(TokenKind::Not.binding_power())?.into(),
+            )),
+            TokenKind::LParen => {
+                let inner = self.expression(0)?;
+                self.expect(TokenKind::RParen)?;
+                Ok(inner)
+            }
+            TokenKind::LBracket => match (self.current(), self.lookahead(1)) {
+                (TokenKind::Number(_) | TokenKind::Colon, _) => self.index_or_slice(Node::This),
+                (TokenKind::Star, TokenKind::RBracket) => {
+                    self.advance();
+                    self.advance();
+                    let rhs = self.projection_rhs(TokenKind::Star.binding_power())?;
+                    Ok(project(Node::This, rhs))
+                }
+                _ => self.multi_list(),
+            },
+            TokenKind::Expref => Err(SyntaxError::new(
+                "expression references ('&') are only valid as function arguments",
+                token.offset,
+            )),
+            other => Err(SyntaxError::new(
+                format!("unexpected {}", other),
+                token.offset,
+            )),
+        }
+    }
+
+    fn led(&mut self, token: Token, left: Node) -> ParseResult<Node> {
+        let binding_power = token.kind.binding_power();
+        match token.kind {
+            TokenKind::Dot => {
+                if *self.current() == TokenKind::Star {
+                    self.advance();
+                    let rhs = self.projection_rhs(binding_power)?;
+                    Ok(project(Node::Struct(left.into(), StructOp::Values), rhs))
+                } else {
+                    let rhs = self.dot_rhs(binding_power)?;
+                    Ok(pipe(left, rhs))
+                }
+            }
+            TokenKind::Pipe => {
+                let rhs = self.expression(binding_power)?;
+                Ok(pipe(left, rhs))
+            }
+            TokenKind::Or => Ok(Node::Or(
+                left.into(),
+                self.expression(binding_power)?.into(),
+            )),
+            TokenKind::And => Ok(Node::And(
+                left.into(),
+                self.expression(binding_power)?.into(),
+            )),
+            TokenKind::Eq => self.comparison(left, binding_power, ComparisonOp::Eq),
+            TokenKind::Ne => self.comparison(left, binding_power, ComparisonOp::Ne),
+            TokenKind::Lt => self.comparison(left, binding_power, ComparisonOp::Lt),
+            TokenKind::Le => self.comparison(left, binding_power, ComparisonOp::Le),
+            TokenKind::Gt => self.comparison(left, binding_power, ComparisonOp::Gt),
+            TokenKind::Ge => self.comparison(left, binding_power, ComparisonOp::Ge),
+            TokenKind::Flatten => {
+                let rhs = self.projection_rhs(binding_power)?;
+                Ok(project(Node::List(left.into(), ListOp::Flatten), rhs))
+            }
+            TokenKind::Filter => self.filter_projection(left),
+            TokenKind::LBracket => match self.current() {
+                TokenKind::Number(_) | TokenKind::Colon => self.index_or_slice(left),
+                _ => {
+                    self.expect(TokenKind::Star)?;
+                    self.expect(TokenKind::RBracket)?;
+                    let rhs = self.projection_rhs(TokenKind::Star.binding_power())?;
+                    Ok(project(left, rhs))
+                }
+            },
+            TokenKind::LParen => match left {
+                Node::Struct(base, StructOp::Field(name)) if matches!(*base, Node::This) => {
+                    let args = self.arguments()?;
+                    self.function(Call {
+                        name: &name,
+                        args,
+                        offset: token.offset,
+                    })
+                }
+                _ => Err(SyntaxError::new(
+                    "only identifiers can be called as functions",
+                    token.offset,
+                )),
+            },
+            other => Err(SyntaxError::new(
+                format!("unexpected {}", other),
+                token.offset,
+            )),
+        }
+    }
+
+    fn comparison(
+        &mut self,
+        left: Node,
+        binding_power: u8,
+        op: fn(Box<Node>) -> ComparisonOp,
+    ) -> ParseResult<Node> {
+        let right = self.expression(binding_power)?;
+        Ok(Node::Compare(left.into(), op(right.into())))
+    }
+
+    fn projection_rhs(&mut self, binding_power: u8) -> ParseResult<Node> {
+        if self.current().binding_power() < PROJECTION_STOP {
+            return Ok(Node::This);
+        }
+        match self.current() {
+            TokenKind::LBracket | TokenKind::Filter => self.expression(binding_power),
+            TokenKind::Dot => {
+                self.advance();
+                self.dot_rhs(binding_power)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn dot_rhs(&mut self, binding_power: u8) -> ParseResult<Node> {
+        match self.current() {
+            TokenKind::Identifier(_) | TokenKind::QuotedIdentifier(_) | TokenKind::Star => {
+                self.expression(binding_power)
+            }
+            TokenKind::LBracket => {
+                self.advance();
+                self.multi_list()
+            }
+            TokenKind::LBrace => {
+                self.advance();
+                self.multi_hash()
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn filter_projection(&mut self, left: Node) -> ParseResult<Node> {
+        let cond = self.expression(0)?;
+        self.expect(TokenKind::RBracket)?;
+        let rhs = if *self.current() == TokenKind::Flatten {
+            Node::This
+        } else {
+            self.projection_rhs(TokenKind::Filter.binding_power())?
+        };
+        Ok(project(
+            Node::List(left.into(), ListOp::Filter(cond.into())),
+            rhs,
+        ))
+    }
+
+    fn index_or_slice(&mut self, left: Node) -> ParseResult<Node> {
+        if *self.current() == TokenKind::Colon || *self.lookahead(1) == TokenKind::Colon {
+            let [start, end, step] = self.slice_parts()?;
+            let sliced = Node::List(left.into(), ListOp::Slice { start, end, step });
+            let rhs = self.projection_rhs(TokenKind::Star.binding_power())?;
+            return Ok(project(sliced, rhs));
+        }
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Number(i) => {
+                self.expect(TokenKind::RBracket)?;
+                Ok(Node::List(left.into(), ListOp::Index(i)))
+            }
+            other => Err(SyntaxError::new(
+                format!("expected an index, found {}", other),
+                token.offset,
+            )),
+        }
+    }
+
+    fn slice_parts(&mut self) -> ParseResult<[Option<isize>; 3]> {
+        let mut parts = [None; 3];
+        let mut index = 0;
+        loop {
+            match self.current().clone() {
+                TokenKind::RBracket => {
+                    self.advance();
+                    break;
+                }
+                TokenKind::Colon if index < 2 => {
+                    index += 1;
+                    self.advance();
+                }
+                TokenKind::Number(n) if parts[index].is_none() => {
+                    parts[index] = Some(n);
+                    self.advance();
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+        if parts[2] == Some(0) {
+            return Err(SyntaxError::new("slice step cannot be 0", self.offset()));
+        }
+        Ok(parts)
+    }
+
+    fn multi_list(&mut self) -> ParseResult<Node> {
+        let mut items = Vec::new();
+        loop {
+            items.push(self.expression(0)?);
+            match self.current() {
+                TokenKind::Comma => {
+                    self.advance();
+                }
+                TokenKind::RBracket => {
+                    self.advance();
+                    return Ok(Node::MultiList(items));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn multi_hash(&mut self) -> ParseResult<Node> {
+        let mut items = Vec::new();
+        loop {
+            let token = self.advance();
+            let key = match token.kind {
+                TokenKind::Identifier(name) | TokenKind::QuotedIdentifier(name) => name,
+                other => {
+                    return Err(SyntaxError::new(
+                        format!("expected a multiselect key, found {}", other),
+                        token.offset,
+                    ))
+                }
+            };
+            self.expect(TokenKind::Colon)?;
+            items.push((key, self.expression(0)?));
+            match self.current() {
+                TokenKind::Comma => {
+                    self.advance();
+                }
+                TokenKind::RBrace => {
+                    self.advance();
+                    return Ok(Node::MultiHash(items));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn arguments(&mut self) -> ParseResult<Vec<Arg>> {
+        let mut args = Vec::new();
+        if *self.current() == TokenKind::RParen {
+            self.advance();
+            return Ok(args);
+        }
+        loop {
+            let offset = self.offset();
+            let is_ref = *self.current() == TokenKind::Expref;
+            if is_ref {
+                self.advance();
+            }
+            args.push(Arg {
+                node: self.expression(0)?,
+                is_ref,
+                offset,
+            });
+            match self.current() {
+                TokenKind::Comma => {
+                    self.advance();
+                }
+                TokenKind::RParen => {
+                    self.advance();
+                    return Ok(args);
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn json_literal(&self, raw: &str, offset: usize) -> ParseResult<Node> {
+        let loads = self
+            .py
+            .import(pylibs::JSON)
+            .and_then(|json| json.getattr("loads"))
+            .map_err(|err| SyntaxError::new(err.to_string(), offset))?;
+        loads
+            .call1((raw,))
+            .or_else(|_| loads.call1((format!("\"{}\"", raw.trim_start()),)))
+            .map(|value| Node::Literal(PyObjectWrapper::new(&value)))
+            .map_err(|_| SyntaxError::new(format!("invalid JSON literal `{}`", raw), offset))
+    }
+
+    fn literal_str(&self, function: &str, arg: Arg) -> ParseResult<String> {
+        let offset = arg.offset;
+        match expr(function, arg)? {
+            Node::Literal(obj) => obj.0.bind(self.py).extract::<String>().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            SyntaxError::new(
+                format!("function '{}' expects a literal string here", function),
+                offset,
+            )
+        })
+    }
+
+    fn function(&self, call: Call<'_>) -> ParseResult<Node> {
+        let name = call.name;
+        let offset = call.offset;
+        let list_op = |call: Call<'_>, op: ListOp| -> ParseResult<Node> {
+            Ok(Node::List(call.unary()?.into(), op))
+        };
+        let by_key = |call: Call<'_>, op: fn(Box<Node>) -> ListOp| -> ParseResult<Node> {
+            let [list, key] = call.exact::<2>()?;
+            Ok(Node::List(
+                expr(name, list)?.into(),
+                op(expref(name, key)?.into()),
+            ))
+        };
+        match name {
+            "abs" => Ok(Node::Scalar(call.unary()?.into(), ScalarOp::Abs)),
+            "ceil" => Ok(Node::Scalar(call.unary()?.into(), ScalarOp::Ceil)),
+            "floor" => Ok(Node::Scalar(call.unary()?.into(), ScalarOp::Floor)),
+            "avg" => list_op(call, ListOp::Avg),
+            "max" => list_op(call, ListOp::Max),
+            "min" => list_op(call, ListOp::Min),
+            "sum" => list_op(call, ListOp::Sum),
+            "sort" => list_op(call, ListOp::Sort),
+            "keys" => Ok(Node::Struct(call.unary()?.into(), StructOp::Keys)),
+            "values" => Ok(Node::Struct(call.unary()?.into(), StructOp::Values)),
+            "length" => Ok(pipe(
+                call.unary()?,
+                Node::Coalesce(vec![
+                    Node::List(Node::This.into(), ListOp::Length),
+                    Node::Str(Node::This.into(), StrOp::Length),
+                    Node::List(
+                        Node::Struct(Node::This.into(), StructOp::Keys).into(),
+                        ListOp::Length,
+                    ),
+                ]),
+            )),
+            "reverse" => Ok(pipe(
+                call.unary()?,
+                Node::Coalesce(vec![
+                    Node::List(Node::This.into(), ListOp::Reverse),
+                    Node::Str(Node::This.into(), StrOp::Reverse),
+                ]),
+            )),
+            "not_null" => Ok(Node::Coalesce(call.variadic(1)?)),
+            "merge" => Ok(Node::Merge(call.variadic(1)?)),
+            "map" => {
+                let [key, list] = call.exact::<2>()?;
+                Ok(Node::List(
+                    expr(name, list)?.into(),
+                    ListOp::Map(expref(name, key)?.into()),
+                ))
+            }
+            "sort_by" => by_key(call, ListOp::SortBy),
+            "min_by" => by_key(call, ListOp::MinBy),
+            "max_by" => by_key(call, ListOp::MaxBy),
+            "join" => {
+                let [glue, list] = call.exact::<2>()?;
+                let glue = self.literal_str(name, glue)?;
+                Ok(Node::List(expr(name, list)?.into(), ListOp::Join(glue)))
+            }
+            "starts_with" | "ends_with" => {
+                let [subject, affix] = call.exact::<2>()?;
+                let affix = self.literal_str(name, affix)?;
+                let op = if name == "starts_with" {
+                    StrOp::StartsWith(affix)
+                } else {
+                    StrOp::EndsWith(affix)
+                };
+                Ok(Node::Str(expr(name, subject)?.into(), op))
+            }
+            "contains" => {
+                let [subject, search] = call.exact::<2>()?;
+                let subject = expr(name, subject)?;
+                let search = expr(name, search)?;
+                let needle = match &search {
+                    Node::Literal(obj) => obj.0.bind(self.py).extract::<String>().ok(),
+                    _ => None,
+                };
+                match needle {
+                    Some(needle) => Ok(pipe(
+                        subject,
+                        Node::Coalesce(vec![
+                            Node::List(Node::This.into(), ListOp::Contains(search.into())),
+                            Node::Str(Node::This.into(), StrOp::Contains(needle)),
+                        ]),
+                    )),
+                    None => Ok(Node::List(subject.into(), ListOp::Contains(search.into()))),
+                }
+            }
+            "to_array" | "to_string" | "to_number" | "type" => Err(SyntaxError::new(
+                format!("function '{}' has no dictexprs equivalent", name),
+                offset,
+            )),
+            _ => Err(SyntaxError::new(
+                format!("unknown function '{}'", name),
+                offset,
+            )),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::nodes::{ListOp, Node, StructOp};
+use std::collections::BTreeMap;
+
+/// The parts of a JSON value an expression can observe.
+///
+/// Lists are transparent: a projection applied to a list applies to each of its items,
+/// so that `users[*].name` only keeps the `name` of every user.
+/// Scalars are always kept whole.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Projection {
+    /// The value is used as a whole (returned, compared, tested for truthiness...).
+    All,
+    /// Only these keys of the objects are read, and the kind of the value is checked.
+    Fields(BTreeMap<String, Projection>),
+}
+
+impl Projection {
+    pub fn none() -> Self {
+        Self::Fields(BTreeMap::new())
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Projection> {
+        match self {
+            Self::All => Some(self),
+            Self::Fields(fields) => fields.get(name),
+        }
+    }
+
+    fn union(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Fields(mut left), Self::Fields(right)) => {
+                for (name, projection) in right {
+                    let merged = match left.remove(&name) {
+                        Some(existing) => existing.union(projection),
+                        None => projection,
+                    };
+                    left.insert(name, merged);
+                }
+                Self::Fields(left)
+            }
+            _ => Self::All,
+        }
+    }
+
+    /// What an expression reads from its input when its whole output is used.
+    pub fn of(node: &Node) -> Self {
+        reads(node, Self::All)
+    }
+}
+
+/// Computes what `node` reads from its input, given what is used of its output.
+fn reads(node: &Node, out: Projection) -> Projection {
+    match node {
+        Node::This => out,
+        Node::Literal(_) => Projection::none(),
+        Node::And(a, b) | Node::Or(a, b) => reads(a, Projection::All).union(reads(b, out)),
+        Node::Not(x) => reads(x, Projection::All),
+        Node::Coalesce(items) | Node::Merge(items) | Node::MultiList(items) => {
+            items.iter().fold(Projection::none(), |acc, item| {
+                acc.union(reads(item, out.clone()))
+            })
+        }
+        Node::MultiHash(items) => items.iter().fold(Projection::none(), |acc, (name, item)| {
+            match out.field(name) {
+                Some(used) => acc.union(reads(item, used.clone())),
+                None => acc,
+            }
+        }),
+        Node::List(base, op) => {
+            let (items, args) = op.reads(out);
+            reads(base, items).union(args)
+        }
+        Node::Struct(base, StructOp::Field(name)) => reads(
+            base,
+            Projection::Fields(BTreeMap::from([(name.clone(), out)])),
+        ),
+        Node::Struct(base, StructOp::Keys | StructOp::Values)
+        | Node::Str(base, _)
+        | Node::Scalar(base, _) => reads(base, Projection::All),
+        Node::Compare(base, op) => {
+            reads(base, Projection::All).union(reads(op.other(), Projection::All))
+        }
+        Node::Arithmetic(base, op) => {
+            reads(base, Projection::All).union(reads(op.other(), Projection::All))
+        }
+        Node::Pipe(base, then) => reads(base, reads(then, out)),
+    }
+}
+
+impl ListOp {
+    /// Returns what is read from the list, and from the input through the arguments.
+    fn reads(&self, out: Projection) -> (Projection, Projection) {
+        match self {
+            Self::Index(_) | Self::Slice { .. } | Self::Reverse | Self::Flatten => {
+                (out, Projection::none())
+            }
+            Self::Length => (Projection::none(), Projection::none()),
+            Self::Contains(search) => (Projection::All, reads(search, Projection::All)),
+            Self::Filter(cond) => (out.union(reads(cond, Projection::All)), Projection::none()),
+            Self::Map(key) | Self::Project(key) => (reads(key, out), Projection::none()),
+            Self::FilterMap(cond, key) => (
+                reads(cond, Projection::All).union(reads(key, out)),
+                Projection::none(),
+            ),
+            Self::SortBy(key) | Self::MinBy(key) | Self::MaxBy(key) => {
+                (out.union(reads(key, Projection::All)), Projection::none())
+            }
+            Self::Join(_) | Self::Sort | Self::Max | Self::Min | Self::Sum | Self::Avg => {
+                (Projection::All, Projection::none())
+            }
+        }
+    }
+}
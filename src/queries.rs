@@ -1,14 +1,19 @@
+use crate::explain;
 use crate::nodes;
+use crate::state;
+use crate::value::Fnv;
+use pyo3::basic::CompareOp;
+use pyo3::exceptions::PyTypeError;
 use pyo3::prelude::*;
+use pyo3::types::{PyList, PySlice, PyString, PyTuple};
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 
 fn into_lit(_py: Python<'_>, obj: &Bound<'_, PyAny>) -> PyResult<nodes::Node> {
     if let Ok(expr) = obj.extract::<PyRef<Expr>>() {
         return Ok(expr.node.clone());
     }
-    Ok(nodes::Node::Literal(nodes::PyObjectWrapper(
-        obj.clone().unbind(),
-    )))
+    Ok(nodes::Node::Literal(nodes::PyObjectWrapper::new(obj)))
 }
 
 type OpWrapper<Op> = NameSpaceBuilder<Op, fn(Box<nodes::Node>, Op) -> nodes::Node>;
@@ -73,6 +78,11 @@ impl Expr {
             builder: NameSpaceBuilder::new(self.clone(), nodes::Node::Struct),
         }
     }
+
+    #[getter]
+    pub fn meta(&self) -> ExprMetaNameSpace {
+        ExprMetaNameSpace { expr: self.clone() }
+    }
     pub fn eq(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
         Ok(Self {
             node: nodes::Node::Compare(
@@ -126,6 +136,41 @@ impl Expr {
             ),
         })
     }
+
+    pub fn add(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.arithmetic(py, other, nodes::ArithmeticOp::Add)
+    }
+
+    pub fn sub(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.arithmetic(py, other, nodes::ArithmeticOp::Sub)
+    }
+
+    pub fn mul(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.arithmetic(py, other, nodes::ArithmeticOp::Mul)
+    }
+
+    pub fn truediv(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.arithmetic(py, other, nodes::ArithmeticOp::TrueDiv)
+    }
+
+    pub fn floordiv(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.arithmetic(py, other, nodes::ArithmeticOp::FloorDiv)
+    }
+
+    #[pyo3(name = "mod")]
+    pub fn mod_(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.arithmetic(py, other, nodes::ArithmeticOp::Mod)
+    }
+
+    pub fn pow(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.arithmetic(py, other, nodes::ArithmeticOp::Pow)
+    }
+
+    pub fn neg(&self) -> Self {
+        Self {
+            node: nodes::Node::Scalar(self.node.clone().into(), nodes::ScalarOp::Neg),
+        }
+    }
     pub fn and_(&self, other: &Expr) -> Self {
         Self {
             node: nodes::Node::And(self.node.clone().into(), other.node.clone().into()),
@@ -144,6 +189,148 @@ impl Expr {
         }
     }
 
+    #[classattr]
+    const __hash__: Option<Py<PyAny>> = None;
+
+    fn __bool__(&self) -> PyResult<bool> {
+        Err(PyTypeError::new_err(
+            "the truth value of an Expr is ambiguous, combine expressions with `&`, `|` and `~` instead",
+        ))
+    }
+
+    fn __richcmp__(
+        &self,
+        py: Python<'_>,
+        other: &Bound<'_, PyAny>,
+        op: CompareOp,
+    ) -> PyResult<Self> {
+        match op {
+            CompareOp::Eq => self.eq(py, other),
+            CompareOp::Ne => self.ne(py, other),
+            CompareOp::Lt => self.lt(py, other),
+            CompareOp::Le => self.le(py, other),
+            CompareOp::Gt => self.gt(py, other),
+            CompareOp::Ge => self.ge(py, other),
+        }
+    }
+
+    fn __and__(&self, other: &Expr) -> Self {
+        self.and_(other)
+    }
+
+    fn __or__(&self, other: &Expr) -> Self {
+        self.or_(other)
+    }
+
+    fn __invert__(&self) -> Self {
+        self.not_()
+    }
+
+    fn __add__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.add(py, other)
+    }
+
+    fn __radd__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.rarithmetic(py, other, nodes::ArithmeticOp::Add)
+    }
+
+    fn __sub__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.sub(py, other)
+    }
+
+    fn __rsub__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.rarithmetic(py, other, nodes::ArithmeticOp::Sub)
+    }
+
+    fn __mul__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.mul(py, other)
+    }
+
+    fn __rmul__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.rarithmetic(py, other, nodes::ArithmeticOp::Mul)
+    }
+
+    fn __truediv__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.truediv(py, other)
+    }
+
+    fn __rtruediv__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.rarithmetic(py, other, nodes::ArithmeticOp::TrueDiv)
+    }
+
+    fn __floordiv__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.floordiv(py, other)
+    }
+
+    fn __rfloordiv__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.rarithmetic(py, other, nodes::ArithmeticOp::FloorDiv)
+    }
+
+    fn __mod__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.mod_(py, other)
+    }
+
+    fn __rmod__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        self.rarithmetic(py, other, nodes::ArithmeticOp::Mod)
+    }
+
+    fn __pow__(
+        &self,
+        py: Python<'_>,
+        other: &Bound<'_, PyAny>,
+        modulo: &Bound<'_, PyAny>,
+    ) -> PyResult<Self> {
+        if !modulo.is_none() {
+            return Err(PyTypeError::new_err(
+                "pow() with a modulus is not supported on Expr",
+            ));
+        }
+        self.pow(py, other)
+    }
+
+    fn __rpow__(
+        &self,
+        py: Python<'_>,
+        other: &Bound<'_, PyAny>,
+        modulo: &Bound<'_, PyAny>,
+    ) -> PyResult<Self> {
+        if !modulo.is_none() {
+            return Err(PyTypeError::new_err(
+                "pow() with a modulus is not supported on Expr",
+            ));
+        }
+        self.rarithmetic(py, other, nodes::ArithmeticOp::Pow)
+    }
+
+    fn __neg__(&self) -> Self {
+        self.neg()
+    }
+
+    fn __getitem__(&self, key: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(name) = key.cast::<PyString>() {
+            return Ok(self.struct_().field(name.to_str()?));
+        }
+        if let Ok(slice) = key.cast::<PySlice>() {
+            let bound = |attr: &str| slice.getattr(attr)?.extract::<Option<isize>>();
+            return Ok(self
+                .list()
+                .slice(bound("start")?, bound("stop")?, bound("step")?));
+        }
+        if let Ok(i) = key.extract::<isize>() {
+            return Ok(self.list().get(i));
+        }
+        Err(PyTypeError::new_err(format!(
+            "Expr indices must be integers, slices or strings, not {}",
+            key.get_type().name()?
+        )))
+    }
+
+    pub fn pipe(&self, other: &Expr) -> Self {
+        Self {
+            node: nodes::Node::Pipe(self.node.clone().into(), other.node.clone().into()),
+        }
+    }
+
     pub fn abs(&self) -> Self {
         Self {
             node: nodes::Node::Scalar(self.node.clone().into(), nodes::ScalarOp::Abs),
@@ -161,7 +348,134 @@ impl Expr {
             node: nodes::Node::Scalar(self.node.clone().into(), nodes::ScalarOp::Floor),
         }
     }
+
+    pub fn to_jmespath(&self, py: Python<'_>) -> PyResult<String> {
+        crate::render::to_jmespath(py, &self.node)
+    }
+
+    pub fn to_json_ast(&self, py: Python<'_>) -> PyResult<String> {
+        state::to_json(py, &self.node)
+    }
+
+    #[staticmethod]
+    pub fn from_json_ast(py: Python<'_>, json: &Bound<'_, PyAny>) -> PyResult<Self> {
+        Ok(Self {
+            node: state::from_json(py, json)?,
+        })
+    }
+
+    #[pyo3(signature = (docs, *, parallel=false))]
+    pub fn search_many<'py>(
+        &self,
+        py: Python<'py>,
+        docs: &Bound<'py, PyAny>,
+        parallel: bool,
+    ) -> PyResult<Bound<'py, PyList>> {
+        crate::holder::search_many(
+            py,
+            &crate::optimize::optimize(py, self.node.clone())?,
+            docs,
+            parallel,
+        )
+    }
+
+    pub fn optimize(&self, py: Python<'_>) -> PyResult<Self> {
+        Ok(Self {
+            node: crate::optimize::optimize(py, self.node.clone())?,
+        })
+    }
+
+    #[pyo3(signature = (*, optimized=false))]
+    pub fn explain(&self, py: Python<'_>, optimized: bool) -> PyResult<String> {
+        Ok(if optimized {
+            explain::tree(py, &crate::optimize::optimize(py, self.node.clone())?)
+        } else {
+            explain::tree(py, &self.node)
+        })
+    }
+
+    pub fn __repr__(&self, py: Python<'_>) -> String {
+        explain::compact(py, &self.node)
+    }
+
+    pub fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyTuple>> {
+        (py.get_type::<Self>(), (), state::to_state(py, &self.node)?).into_pyobject(py)
+    }
+
+    pub fn __setstate__(&mut self, state: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.node = state::from_state(state)?;
+        Ok(())
+    }
+
+    pub fn __copy__(&self) -> Self {
+        self.clone()
+    }
+
+    /// Literals are copied as well, since they may be mutable Python objects.
+    pub fn __deepcopy__(&self, py: Python<'_>, memo: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let copied = py
+            .import("copy")?
+            .getattr("deepcopy")?
+            .call1((state::dump(py, &self.node)?, memo))?;
+        Ok(Self {
+            node: state::load(&copied)?,
+        })
+    }
+}
+impl Expr {
+    fn arithmetic(
+        &self,
+        py: Python<'_>,
+        other: &Bound<'_, PyAny>,
+        op: fn(Box<nodes::Node>) -> nodes::ArithmeticOp,
+    ) -> PyResult<Self> {
+        Ok(Self {
+            node: nodes::Node::Arithmetic(
+                self.node.clone().into(),
+                op(into_lit(py, other)?.into()),
+            ),
+        })
+    }
+
+    fn rarithmetic(
+        &self,
+        py: Python<'_>,
+        other: &Bound<'_, PyAny>,
+        op: fn(Box<nodes::Node>) -> nodes::ArithmeticOp,
+    ) -> PyResult<Self> {
+        Ok(Self {
+            node: nodes::Node::Arithmetic(
+                into_lit(py, other)?.into(),
+                op(self.node.clone().into()),
+            ),
+        })
+    }
+}
+/// Questions about the expression itself, rather than expressions built from it.
+#[pyclass(module = "dictexprs", name = "ExprMetaNameSpace")]
+pub struct ExprMetaNameSpace {
+    expr: Expr,
+}
+
+#[pymethods]
+impl ExprMetaNameSpace {
+    /// Whether both expressions have the same tree, literals being compared by JSON value
+    /// whatever the order of their object keys, or with `==` when they are not JSON.
+    pub fn eq(&self, other: &Expr) -> bool {
+        self.expr.node == other.node
+    }
+
+    /// A hash of the tree, equal for expressions that `eq` considers equal.
+    ///
+    /// FNV-1a is used rather than the standard hasher, so that the hash stays the same
+    /// across processes and releases, unless the expression holds literals that are not JSON.
+    pub fn hash(&self) -> u64 {
+        let mut hasher = Fnv::default();
+        self.expr.node.hash(&mut hasher);
+        hasher.finish()
+    }
 }
+
 #[pyclass(module = "dictexprs", name = "ExprStructNameSpace")]
 pub struct ExprStructNameSpace {
     builder: OpWrapper<nodes::StructOp>,
@@ -297,6 +611,7 @@ impl ExprListNameSpace {
 }
 pub mod entryfuncs {
     use super::*;
+    use pyo3::types::{PyDict, PyTuple};
 
     #[pyfunction]
     pub fn element() -> Expr {
@@ -333,9 +648,46 @@ pub mod entryfuncs {
         }
     }
     #[pyfunction]
+    #[pyo3(signature = (*args))]
+    pub fn concat_list(py: Python<'_>, args: &Bound<'_, PyTuple>) -> PyResult<Expr> {
+        Ok(Expr {
+            node: nodes::Node::MultiList(
+                args.iter()
+                    .map(|arg| into_lit(py, &arg))
+                    .collect::<PyResult<_>>()?,
+            ),
+        })
+    }
+    #[pyfunction]
+    #[pyo3(signature = (fields=None, /, **named))]
+    pub fn struct_from(
+        py: Python<'_>,
+        fields: Option<&Bound<'_, PyDict>>,
+        named: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Expr> {
+        let mut items: Vec<(String, nodes::Node)> = Vec::new();
+        for (key, value) in fields.into_iter().chain(named).flat_map(|dict| dict.iter()) {
+            let key = key.extract::<String>()?;
+            let node = into_lit(py, &value)?;
+            match items.iter_mut().find(|(existing, _)| *existing == key) {
+                Some(item) => item.1 = node,
+                None => items.push((key, node)),
+            }
+        }
+        Ok(Expr {
+            node: nodes::Node::MultiHash(items),
+        })
+    }
+    #[pyfunction]
+    pub fn parse(py: Python<'_>, query: &str) -> PyResult<Expr> {
+        Ok(Expr {
+            node: crate::parser::parse(py, query)?,
+        })
+    }
+    #[pyfunction]
     pub fn lit(value: &Bound<'_, PyAny>) -> Expr {
         Expr {
-            node: nodes::Node::Literal(nodes::PyObjectWrapper(value.clone().unbind())),
+            node: nodes::Node::Literal(nodes::PyObjectWrapper::new(value)),
         }
     }
 }
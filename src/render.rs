@@ -0,0 +1,444 @@
+use crate::errors::InvalidArgumentError;
+use crate::nodes::{ComparisonOp, ListOp, Node, ScalarOp, StrOp, StructOp};
+use pyo3::prelude::*;
+
+const PIPE: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const COMPARE: u8 = 5;
+const NOT: u8 = 45;
+const ATOM: u8 = 100;
+
+/// A rendered fragment, with the binding power of its outermost operator.
+struct Rendered {
+    text: String,
+    prec: u8,
+    projection: bool,
+}
+
+impl Rendered {
+    fn atom(text: String) -> Self {
+        Self {
+            text,
+            prec: ATOM,
+            projection: false,
+        }
+    }
+
+    fn projection(text: String) -> Self {
+        Self {
+            text,
+            prec: ATOM,
+            projection: true,
+        }
+    }
+
+    fn operator(text: String, prec: u8) -> Self {
+        Self {
+            text,
+            prec,
+            projection: false,
+        }
+    }
+
+    /// Text usable where `min_prec` is required, parenthesized otherwise.
+    fn at(self, min_prec: u8) -> String {
+        if self.prec < min_prec {
+            format!("({})", self.text)
+        } else {
+            self.text
+        }
+    }
+
+    /// Text usable as the left-hand side of `.field` or `[...]`.
+    fn postfix(self) -> String {
+        if self.projection || self.prec < ATOM {
+            format!("({})", self.text)
+        } else {
+            self.text
+        }
+    }
+}
+
+pub fn to_jmespath(py: Python<'_>, node: &Node) -> PyResult<String> {
+    Ok(render(py, node)?.text)
+}
+
+fn unsupported(what: &str) -> PyErr {
+    InvalidArgumentError::new_err(format!("{} has no JMESPath equivalent", what))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn json_string(s: &str) -> String {
+    let mut output = String::with_capacity(s.len() + 2);
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+fn identifier(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_string()
+    } else {
+        json_string(name)
+    }
+}
+
+fn literal_json(json: &str) -> String {
+    format!("`{}`", json.replace('`', "\\`"))
+}
+
+fn call(name: &str, args: &[String]) -> Rendered {
+    Rendered::atom(format!("{}({})", name, args.join(", ")))
+}
+
+fn render_all(py: Python<'_>, nodes: &[Node]) -> PyResult<Vec<String>> {
+    nodes
+        .iter()
+        .map(|node| Ok(render(py, node)?.text))
+        .collect()
+}
+
+fn binary(py: Python<'_>, a: &Node, op: &str, b: &Node, prec: u8) -> PyResult<Rendered> {
+    let left = render(py, a)?.at(prec);
+    let right = render(py, b)?.at(prec + 1);
+    Ok(Rendered::operator(
+        format!("{} {} {}", left, op, right),
+        prec,
+    ))
+}
+
+/// Recognizes the type-dispatching `Coalesce` that `dx.parse` builds for polymorphic
+/// JMESPath functions, returning the function name and its optional second argument.
+fn polymorphic(node: &Node) -> Option<(&'static str, Option<&Node>)> {
+    let Node::Coalesce(items) = node else {
+        return None;
+    };
+    let on_this = |base: &Node| matches!(base, Node::This);
+    match items.as_slice() {
+        [Node::List(a, ListOp::Length), Node::Str(b, StrOp::Length), Node::List(c, ListOp::Length)]
+            if on_this(a)
+                && on_this(b)
+                && matches!(&**c, Node::Struct(keys, StructOp::Keys) if on_this(keys)) =>
+        {
+            Some(("length", None))
+        }
+        [Node::List(a, ListOp::Reverse), Node::Str(b, StrOp::Reverse)]
+            if on_this(a) && on_this(b) =>
+        {
+            Some(("reverse", None))
+        }
+        [Node::List(a, ListOp::Contains(search)), Node::Str(b, StrOp::Contains(_))]
+            if on_this(a) && on_this(b) =>
+        {
+            Some(("contains", Some(&**search)))
+        }
+        _ => None,
+    }
+}
+
+fn polymorphic_call(
+    py: Python<'_>,
+    name: &str,
+    subject: String,
+    extra: Option<&Node>,
+) -> PyResult<Rendered> {
+    let mut args = vec![subject];
+    if let Some(extra) = extra {
+        args.push(render(py, extra)?.text);
+    }
+    Ok(call(name, &args))
+}
+
+/// Guards a JMESPath function that accepts more types than the dictexprs operation it stands
+/// for, so that it returns null like the operation does when `checked` is not of `json_type`.
+///
+/// The result is wrapped in a list while the guard is applied, since `&&` and `||` would
+/// otherwise turn falsy results such as `false` or `[]` into null.
+fn guarded(value: String, checked: &str, json_type: &str, function: Rendered) -> Rendered {
+    Rendered::atom(format!(
+        "({} | type({}) == '{}' && [{}] || `[null]`)[0]",
+        value, checked, json_type, function.text
+    ))
+}
+
+/// Calls `name` on `subject` and an optional literal argument, if `subject` is of `json_type`.
+fn typed_call(name: &str, json_type: &str, subject: String, literal: Option<String>) -> Rendered {
+    let args: Vec<String> = std::iter::once("@".to_string()).chain(literal).collect();
+    guarded(subject, "@", json_type, call(name, &args))
+}
+
+/// Same as `typed_call` for `contains`, when the searched value depends on the current one and
+/// must be evaluated before `subject` replaces it.
+fn typed_contains(json_type: &str, subject: String, search: String) -> Rendered {
+    guarded(
+        format!("[{}, {}]", subject, search),
+        "@[0]",
+        json_type,
+        call("contains", &["@[0]".to_string(), "@[1]".to_string()]),
+    )
+}
+
+/// `list.flatten`, which keeps null items where a `[]` projection drops them.
+///
+/// Each item is wrapped in a list that survives the projection, the null ones with a literal
+/// since a multiselect of null is null, and unwrapped by `map`, which keeps nulls.
+fn flatten(subject: String) -> Rendered {
+    let wrap = "(@ == `null` && `[null]` || [@])";
+    let wrap_all = format!(
+        "(type(@) == 'array' && [map(&{}, @)] || @ == `null` && `[[[null]]]` || [[[@]]])[0]",
+        wrap
+    );
+    guarded(
+        subject,
+        "@",
+        "array",
+        call(
+            "map",
+            &["&@[0]".to_string(), format!("map(&{}, @)[]", wrap_all)],
+        ),
+    )
+}
+
+/// `base[]`, which starts a projection dropping the null results of its right-hand side,
+/// like `list.project` does.
+fn flatten_projection(py: Python<'_>, base: &Node) -> PyResult<String> {
+    // `[]` terminates any projection on its left, so it never needs parentheses.
+    let rendered = render(py, base)?;
+    let head = if rendered.prec == ATOM {
+        rendered.text
+    } else {
+        rendered.postfix()
+    };
+    Ok(format!("{}[]", head))
+}
+
+/// Renders the right-hand side of a `[*]` projection.
+fn projection_rhs(py: Python<'_>, rhs: &Node) -> PyResult<String> {
+    if let Node::This = rhs {
+        return Ok(String::new());
+    }
+    let rendered = render(py, rhs)?;
+    if rendered.prec == ATOM {
+        if let Some(rest) = rendered.text.strip_prefix("@[") {
+            return Ok(format!("[{}", rest));
+        }
+        let first = rendered.text.chars().next().unwrap_or_default();
+        if first.is_ascii_alphabetic() || matches!(first, '_' | '"' | '[' | '{') {
+            return Ok(format!(".{}", rendered.text));
+        }
+    }
+    // Flattening the single item lists keeps the items in the projection, where an index would
+    // only keep the first one.
+    Ok(format!(".[{}][]", rendered.text))
+}
+
+fn render(py: Python<'_>, node: &Node) -> PyResult<Rendered> {
+    match node {
+        Node::This => Ok(Rendered::atom("@".to_string())),
+        Node::Literal(obj) => obj
+            .to_json(py)
+            .map(|json| Rendered::atom(literal_json(&json)))
+            .map_err(|_| unsupported(&format!("literal {:?}", obj))),
+        Node::And(a, b) => binary(py, a, "&&", b, AND),
+        Node::Or(a, b) => binary(py, a, "||", b, OR),
+        Node::Not(x) => Ok(Rendered::operator(
+            format!("!{}", render(py, x)?.at(NOT)),
+            NOT,
+        )),
+        Node::Coalesce(items) => match polymorphic(node) {
+            Some((name, extra)) => polymorphic_call(py, name, "@".to_string(), extra),
+            None if items.is_empty() => Ok(Rendered::atom("`null`".to_string())),
+            None => Ok(call("not_null", &render_all(py, items)?)),
+        },
+        Node::Merge(items) if items.is_empty() => Ok(Rendered::atom("`{}`".to_string())),
+        Node::Merge(items) => Ok(call("merge", &render_all(py, items)?)),
+        Node::Pipe(base, then) => match polymorphic(then) {
+            Some((name, extra)) => polymorphic_call(py, name, render(py, base)?.text, extra),
+            None => {
+                let left = render(py, base)?.at(PIPE);
+                let right = render(py, then)?.at(PIPE + 1);
+                Ok(Rendered::operator(format!("{} | {}", left, right), PIPE))
+            }
+        },
+        Node::MultiList(items) if items.is_empty() => Err(unsupported("an empty concat_list")),
+        Node::MultiList(items) => Ok(Rendered::atom(format!(
+            "[{}]",
+            render_all(py, items)?.join(", ")
+        ))),
+        Node::MultiHash(items) => {
+            let pairs = items
+                .iter()
+                .map(|(key, item)| Ok(format!("{}: {}", identifier(key), render(py, item)?.text)))
+                .collect::<PyResult<Vec<_>>>()?;
+            Ok(Rendered::atom(format!("{{{}}}", pairs.join(", "))))
+        }
+        Node::List(base, op) => op.render(py, base),
+        Node::Str(base, op) => op.render(py, base),
+        Node::Struct(base, op) => op.render(py, base),
+        Node::Scalar(base, op) => op.render(py, base),
+        Node::Compare(base, op) => op.render(py, base),
+        Node::Arithmetic(..) => Err(unsupported("arithmetic")),
+    }
+}
+
+impl ListOp {
+    fn render(&self, py: Python<'_>, base: &Node) -> PyResult<Rendered> {
+        let target = || -> PyResult<String> { Ok(render(py, base)?.text) };
+        let postfix = || -> PyResult<String> { Ok(render(py, base)?.postfix()) };
+        let by_key = |name: &str, key: &Node| -> PyResult<Rendered> {
+            Ok(call(
+                name,
+                &[target()?, format!("&{}", render(py, key)?.text)],
+            ))
+        };
+        match self {
+            Self::Index(i) => Ok(Rendered::atom(format!("{}[{}]", postfix()?, i))),
+            Self::Slice { start, end, step } => {
+                let bound = |b: &Option<isize>| b.map(|v| v.to_string()).unwrap_or_default();
+                let step = step.map(|s| format!(":{}", s)).unwrap_or_default();
+                Ok(Rendered::projection(format!(
+                    "{}[{}:{}{}]",
+                    postfix()?,
+                    bound(start),
+                    bound(end),
+                    step
+                )))
+            }
+            Self::Flatten => Ok(flatten(target()?)),
+            Self::Filter(cond) => Ok(Rendered::projection(format!(
+                "{}[?{}]",
+                postfix()?,
+                render(py, cond)?.text
+            ))),
+            Self::Project(rhs) => {
+                let head = match base {
+                    Node::Struct(inner, StructOp::Values) => match &**inner {
+                        Node::This => "*".to_string(),
+                        inner => format!("{}.*", render(py, inner)?.postfix()),
+                    },
+                    Node::List(inner, ListOp::Flatten) => flatten_projection(py, inner)?,
+                    Node::List(_, ListOp::Filter(_) | ListOp::Slice { .. }) => {
+                        let rendered = render(py, base)?;
+                        if rendered.prec == ATOM {
+                            rendered.text
+                        } else {
+                            format!("{}[*]", rendered.postfix())
+                        }
+                    }
+                    _ => format!("{}[*]", postfix()?),
+                };
+                Ok(Rendered::projection(format!(
+                    "{}{}",
+                    head,
+                    projection_rhs(py, rhs)?
+                )))
+            }
+            Self::Length => Ok(typed_call("length", "array", target()?, None)),
+            Self::Reverse => Ok(typed_call("reverse", "array", target()?, None)),
+            Self::Sort => Ok(call("sort", &[target()?])),
+            Self::Max => Ok(call("max", &[target()?])),
+            Self::Min => Ok(call("min", &[target()?])),
+            Self::Sum => Ok(call("sum", &[target()?])),
+            Self::Avg => Ok(call("avg", &[target()?])),
+            Self::Contains(search) => {
+                let rendered = render(py, search)?.text;
+                Ok(match **search {
+                    Node::Literal(_) => typed_call("contains", "array", target()?, Some(rendered)),
+                    _ => typed_contains("array", target()?, rendered),
+                })
+            }
+            Self::Join(glue) => Ok(call("join", &[literal_json(&json_string(glue)), target()?])),
+            Self::Map(key) => Ok(call(
+                "map",
+                &[format!("&{}", render(py, key)?.text), target()?],
+            )),
+            Self::FilterMap(cond, key) => {
+                let filtered = Node::List(base.clone().into(), Self::Filter(cond.clone()));
+                Self::Map(key.clone()).render(py, &filtered)
+            }
+            Self::SortBy(key) => by_key("sort_by", key),
+            Self::MinBy(key) => by_key("min_by", key),
+            Self::MaxBy(key) => by_key("max_by", key),
+        }
+    }
+}
+
+impl StrOp {
+    fn render(&self, py: Python<'_>, base: &Node) -> PyResult<Rendered> {
+        let target = render(py, base)?.text;
+        let string = |s: &str| literal_json(&json_string(s));
+        match self {
+            Self::Slice { .. } => Err(unsupported("str.slice")),
+            Self::Reverse => Ok(typed_call("reverse", "string", target, None)),
+            Self::Length => Ok(typed_call("length", "string", target, None)),
+            Self::Contains(search) => Ok(typed_call(
+                "contains",
+                "string",
+                target,
+                Some(string(search)),
+            )),
+            Self::StartsWith(prefix) => Ok(call("starts_with", &[target, string(prefix)])),
+            Self::EndsWith(suffix) => Ok(call("ends_with", &[target, string(suffix)])),
+        }
+    }
+}
+
+impl StructOp {
+    fn render(&self, py: Python<'_>, base: &Node) -> PyResult<Rendered> {
+        match self {
+            Self::Field(name) => match base {
+                Node::This => Ok(Rendered::atom(identifier(name))),
+                _ => Ok(Rendered::atom(format!(
+                    "{}.{}",
+                    render(py, base)?.postfix(),
+                    identifier(name)
+                ))),
+            },
+            Self::Keys => Ok(call("keys", &[render(py, base)?.text])),
+            Self::Values => Ok(call("values", &[render(py, base)?.text])),
+        }
+    }
+}
+
+impl ScalarOp {
+    fn render(&self, py: Python<'_>, base: &Node) -> PyResult<Rendered> {
+        let name = match self {
+            Self::Abs => "abs",
+            Self::Ceil => "ceil",
+            Self::Floor => "floor",
+            Self::Neg => return Err(unsupported("arithmetic")),
+        };
+        Ok(call(name, &[render(py, base)?.text]))
+    }
+}
+
+impl ComparisonOp {
+    fn render(&self, py: Python<'_>, base: &Node) -> PyResult<Rendered> {
+        let (op, other) = match self {
+            Self::Eq(other) => ("==", other),
+            Self::Ne(other) => ("!=", other),
+            Self::Lt(other) => ("<", other),
+            Self::Le(other) => ("<=", other),
+            Self::Gt(other) => (">", other),
+            Self::Ge(other) => (">=", other),
+        };
+        binary(py, base, op, other, COMPARE)
+    }
+}
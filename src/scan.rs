@@ -0,0 +1,296 @@
+use crate::errors::{self, InvalidArgumentError};
+use crate::interrupt;
+use crate::json::{self, ArrayItems};
+use crate::native::{self, list::Step};
+use crate::nodes;
+use crate::optimize::optimize;
+use crate::projection::Projection;
+use crate::queries::Expr;
+use crate::value::{Interner, Value};
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyList;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// What to do with a line that is not valid JSON.
+#[derive(Clone, Copy)]
+pub(crate) enum OnError {
+    Raise,
+    Skip,
+    Null,
+}
+
+impl<'py> FromPyObject<'_, 'py> for OnError {
+    type Error = PyErr;
+
+    fn extract(obj: Borrowed<'_, 'py, PyAny>) -> PyResult<Self> {
+        match obj.extract::<&str>()? {
+            "raise" => Ok(Self::Raise),
+            "skip" => Ok(Self::Skip),
+            "null" => Ok(Self::Null),
+            other => Err(InvalidArgumentError::new_err(format!(
+                "on_error must be 'raise', 'skip' or 'null', not '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Reads one document per line, skipping blank lines.
+struct Lines {
+    reader: BufReader<File>,
+    buffer: Vec<u8>,
+    interner: Interner,
+    line: usize,
+    on_error: OnError,
+}
+
+impl Lines {
+    fn open(path: &Path, on_error: OnError) -> PyResult<Self> {
+        let file = File::open(path).map_err(|err| json::with_path(path, err))?;
+        Ok(Self {
+            reader: BufReader::new(file),
+            buffer: Vec::new(),
+            interner: Interner::default(),
+            line: 0,
+            on_error,
+        })
+    }
+
+    fn next_result(
+        &mut self,
+        node: &nodes::Node,
+        projection: &Projection,
+    ) -> PyResult<Option<Value>> {
+        loop {
+            interrupt::check_detached()?;
+            self.buffer.clear();
+            if self.reader.read_until(b'\n', &mut self.buffer)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            if self.buffer.trim_ascii().is_empty() {
+                continue;
+            }
+            match json::from_slice(&self.buffer, &mut self.interner, projection) {
+                Ok(document) => {
+                    return Ok(Some(native::match_any(node, &document)?.into_owned()));
+                }
+                Err(err) => match self.on_error {
+                    OnError::Raise => {
+                        return Err(PyValueError::new_err(format!(
+                            "invalid JSON on line {}: {}",
+                            self.line, err
+                        )));
+                    }
+                    OnError::Skip => continue,
+                    OnError::Null => return Ok(Some(Value::Null)),
+                },
+            }
+        }
+    }
+}
+
+#[pyclass(module = "dictexprs", name = "NdjsonScan")]
+pub struct NdjsonScan {
+    path: PathBuf,
+    on_error: OnError,
+}
+
+#[pymethods]
+impl NdjsonScan {
+    pub fn query(&self, py: Python<'_>, expr: &Expr) -> PyResult<NdjsonQuery> {
+        let node = optimize(py, expr.node.clone())?;
+        Ok(NdjsonQuery {
+            path: self.path.clone(),
+            on_error: self.on_error,
+            projection: Projection::of(&node),
+            node,
+        })
+    }
+}
+
+#[pyclass(module = "dictexprs", name = "NdjsonQuery")]
+pub struct NdjsonQuery {
+    path: PathBuf,
+    on_error: OnError,
+    node: nodes::Node,
+    projection: Projection,
+}
+
+#[pymethods]
+impl NdjsonQuery {
+    pub fn collect<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let results = interrupt::watch(None, || {
+            py.detach(|| {
+                let mut lines = Lines::open(&self.path, self.on_error)?;
+                let mut results = Vec::new();
+                while let Some(result) = lines.next_result(&self.node, &self.projection)? {
+                    results.push(result);
+                }
+                Ok(results)
+            })
+        })
+        .map_err(|err| errors::with_context(py, err, &self.node, None))?;
+        let output = PyList::empty(py);
+        for result in results {
+            output.append(result.to_py(py)?)?;
+        }
+        Ok(output)
+    }
+
+    pub fn __iter__(&self, py: Python<'_>) -> PyResult<NdjsonIter> {
+        Ok(NdjsonIter {
+            lines: py.detach(|| Lines::open(&self.path, self.on_error))?,
+            node: self.node.clone(),
+            projection: self.projection.clone(),
+        })
+    }
+}
+
+#[pyclass(module = "dictexprs", name = "NdjsonIter")]
+pub struct NdjsonIter {
+    lines: Lines,
+    node: nodes::Node,
+    projection: Projection,
+}
+
+#[pymethods]
+impl NdjsonIter {
+    pub fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    pub fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        let Self {
+            lines,
+            node,
+            projection,
+        } = self;
+        interrupt::watch(None, || py.detach(|| lines.next_result(node, projection)))
+            .map_err(|err| errors::with_context(py, err, node, None))?
+            .map(|result| result.to_py(py))
+            .transpose()
+    }
+}
+
+#[pyclass(module = "dictexprs", name = "JsonScan")]
+pub struct JsonScan {
+    path: PathBuf,
+}
+
+#[pymethods]
+impl JsonScan {
+    pub fn query(&self, py: Python<'_>, expr: &Expr) -> PyResult<JsonQuery> {
+        let node = optimize(py, expr.node.clone())?;
+        Ok(JsonQuery {
+            path: self.path.clone(),
+            projection: Projection::of(&node),
+            node,
+        })
+    }
+}
+
+#[pyclass(module = "dictexprs", name = "JsonQuery")]
+pub struct JsonQuery {
+    path: PathBuf,
+    node: nodes::Node,
+    projection: Projection,
+}
+
+#[pymethods]
+impl JsonQuery {
+    pub fn collect<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        interrupt::watch(None, || {
+            py.detach(|| {
+                let json = json::read(&self.path)?;
+                let document = json::from_slice(&json, &mut Interner::default(), &self.projection)
+                    .map_err(json::into_pyerr)?;
+                Ok(native::match_any(&self.node, &document)?.into_owned())
+            })
+        })
+        .map_err(|err| errors::with_context(py, err, &self.node, None))?
+        .to_py(py)
+    }
+
+    pub fn __iter__(&self, py: Python<'_>) -> PyResult<JsonIter> {
+        let steps = Step::chain(&self.node).ok_or_else(|| {
+            PyTypeError::new_err(
+                "only chains of list.filter and list.map over the root array can be streamed, use collect() instead",
+            )
+        })?;
+        let file =
+            py.detach(|| File::open(&self.path).map_err(|err| json::with_path(&self.path, err)))?;
+        Ok(JsonIter {
+            items: ArrayItems::new(BufReader::new(file)),
+            interner: Interner::default(),
+            index: 0,
+            node: self.node.clone(),
+            steps,
+            projection: self.projection.clone(),
+        })
+    }
+}
+
+#[pyclass(module = "dictexprs", name = "JsonIter")]
+pub struct JsonIter {
+    items: ArrayItems<BufReader<File>>,
+    interner: Interner,
+    index: usize,
+    /// The streamed query, reported in evaluation errors.
+    node: nodes::Node,
+    steps: Vec<Step>,
+    projection: Projection,
+}
+
+impl JsonIter {
+    fn next_result(&mut self) -> PyResult<Option<Value>> {
+        'items: while let Some(item) = self.items.next_item()? {
+            interrupt::check_detached()?;
+            self.index += 1;
+            let mut element = json::from_slice(item, &mut self.interner, &self.projection)
+                .map_err(|err| {
+                    PyValueError::new_err(format!(
+                        "invalid JSON in array item {}: {}",
+                        self.index - 1,
+                        err
+                    ))
+                })?;
+            for step in &self.steps {
+                match step.apply(element)? {
+                    Some(next) => element = next,
+                    None => continue 'items,
+                }
+            }
+            return Ok(Some(element));
+        }
+        Ok(None)
+    }
+}
+
+#[pymethods]
+impl JsonIter {
+    pub fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    pub fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        interrupt::watch(None, || py.detach(|| self.next_result()))
+            .map_err(|err| errors::with_context(py, err, &self.node, None))?
+            .map(|result| result.to_py(py))
+            .transpose()
+    }
+}
+
+#[pyfunction]
+pub fn scan_json(path: PathBuf) -> JsonScan {
+    JsonScan { path }
+}
+
+#[pyfunction]
+#[pyo3(signature = (path, *, on_error=OnError::Raise))]
+pub fn scan_ndjson(path: PathBuf, on_error: OnError) -> NdjsonScan {
+    NdjsonScan { path, on_error }
+}
@@ -0,0 +1,483 @@
+use crate::errors::InvalidArgumentError;
+use crate::eval;
+use crate::limits;
+use crate::nodes::{
+    ArithmeticOp, ComparisonOp, ListOp, Node, PyObjectWrapper, ScalarOp, StrOp, StructOp,
+};
+use pyo3::conversion::FromPyObjectOwned;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyTuple};
+use pyo3::IntoPyObjectExt;
+use std::cell::Cell;
+
+/// Bumped whenever the layout below changes, so that old pickles and JSON ASTs are not misread.
+pub const VERSION: u32 = 1;
+
+/// The state an expression is pickled to: the format version, then the tree of nodes.
+pub fn to_state<'py>(py: Python<'py>, node: &Node) -> PyResult<Bound<'py, PyAny>> {
+    (VERSION, dump(py, node)?).into_bound_py_any(py)
+}
+
+pub fn from_state(state: &Bound<'_, PyAny>) -> PyResult<Node> {
+    let (version, tree) = state.extract::<(u32, Bound<'_, PyAny>)>()?;
+    if version != VERSION {
+        return Err(InvalidArgumentError::new_err(format!(
+            "unsupported expression state version {}, expected {}",
+            version, VERSION
+        )));
+    }
+    load(&tree)
+}
+
+/// The JSON text of an expression: `{"version": 1, "expr": ...}`, where `expr` is the
+/// output of `dump` with its tuples written as arrays.
+pub fn to_json(py: Python<'_>, node: &Node) -> PyResult<String> {
+    check_json_literals(node)?;
+    let ast = PyDict::new(py);
+    ast.set_item("version", VERSION)?;
+    ast.set_item("expr", dump(py, node)?)?;
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("allow_nan", false)?;
+    py.import(eval::pylibs::JSON)?
+        .getattr("dumps")?
+        .call((ast,), Some(&kwargs))?
+        .extract()
+}
+
+fn check_json_literals(node: &Node) -> PyResult<()> {
+    if let Node::Literal(obj) = node {
+        if obj.1.is_none() {
+            return Err(InvalidArgumentError::new_err(format!(
+                "literal {:?} is not a JSON value and cannot be written to a JSON AST",
+                obj
+            )));
+        }
+    }
+    let mut checked = Ok(());
+    node.for_each_child(&mut |child| {
+        if checked.is_ok() {
+            checked = check_json_literals(child);
+        }
+    });
+    checked
+}
+
+pub fn from_json(py: Python<'_>, json: &Bound<'_, PyAny>) -> PyResult<Node> {
+    let ast = py
+        .import(eval::pylibs::JSON)?
+        .getattr("loads")?
+        .call1((json,))
+        .map_err(|err| {
+            let invalid =
+                InvalidArgumentError::new_err(format!("invalid JSON AST: {}", err.value(py)));
+            invalid.set_cause(py, Some(err));
+            invalid
+        })?;
+    let (Ok(version), Ok(expr)) = (ast.get_item("version"), ast.get_item("expr")) else {
+        return Err(InvalidArgumentError::new_err(
+            "a JSON AST must be an object with \"version\" and \"expr\" keys",
+        ));
+    };
+    if version.extract::<u32>().ok() != Some(VERSION) {
+        return Err(InvalidArgumentError::new_err(format!(
+            "unsupported JSON AST version {}, expected {}",
+            version.repr()?,
+            VERSION
+        )));
+    }
+    load(&expr)
+}
+
+/// Converts an expression to nested tuples of plain Python values.
+///
+/// Each node is a tuple starting with its name, such as `("list", base, ("map", key))`,
+/// and literals keep their Python object as is.
+pub fn dump<'py>(py: Python<'py>, node: &Node) -> PyResult<Bound<'py, PyAny>> {
+    let dump_all = |nodes: &[Node]| -> PyResult<Bound<'py, PyAny>> {
+        let items = nodes
+            .iter()
+            .map(|node| dump(py, node))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(PyList::new(py, items)?.into_any())
+    };
+    match node {
+        Node::This => tagged(py, "this", []),
+        Node::Literal(obj) => tagged(py, "literal", [obj.0.bind(py).clone()]),
+        Node::And(a, b) => tagged(py, "and", [dump(py, a)?, dump(py, b)?]),
+        Node::Or(a, b) => tagged(py, "or", [dump(py, a)?, dump(py, b)?]),
+        Node::Not(x) => tagged(py, "not", [dump(py, x)?]),
+        Node::Coalesce(items) => tagged(py, "coalesce", [dump_all(items)?]),
+        Node::Merge(items) => tagged(py, "merge", [dump_all(items)?]),
+        Node::List(base, op) => tagged(py, "list", [dump(py, base)?, dump_list_op(py, op)?]),
+        Node::Str(base, op) => tagged(py, "str", [dump(py, base)?, dump_str_op(py, op)?]),
+        Node::Struct(base, op) => {
+            let op = match op {
+                StructOp::Field(name) => tagged(py, "field", [name.into_bound_py_any(py)?])?,
+                StructOp::Keys => tagged(py, "keys", [])?,
+                StructOp::Values => tagged(py, "values", [])?,
+            };
+            tagged(py, "struct", [dump(py, base)?, op])
+        }
+        Node::Scalar(base, op) => {
+            let name = match op {
+                ScalarOp::Abs => "abs",
+                ScalarOp::Ceil => "ceil",
+                ScalarOp::Floor => "floor",
+                ScalarOp::Neg => "neg",
+            };
+            tagged(py, "scalar", [dump(py, base)?, tagged(py, name, [])?])
+        }
+        Node::Compare(base, op) => {
+            let name = match op {
+                ComparisonOp::Eq(_) => "eq",
+                ComparisonOp::Ne(_) => "ne",
+                ComparisonOp::Lt(_) => "lt",
+                ComparisonOp::Le(_) => "le",
+                ComparisonOp::Gt(_) => "gt",
+                ComparisonOp::Ge(_) => "ge",
+            };
+            let op = tagged(py, name, [dump(py, op.other())?])?;
+            tagged(py, "compare", [dump(py, base)?, op])
+        }
+        Node::Arithmetic(base, op) => {
+            let name = match op {
+                ArithmeticOp::Add(_) => "add",
+                ArithmeticOp::Sub(_) => "sub",
+                ArithmeticOp::Mul(_) => "mul",
+                ArithmeticOp::TrueDiv(_) => "truediv",
+                ArithmeticOp::FloorDiv(_) => "floordiv",
+                ArithmeticOp::Mod(_) => "mod",
+                ArithmeticOp::Pow(_) => "pow",
+            };
+            let op = tagged(py, name, [dump(py, op.other())?])?;
+            tagged(py, "arithmetic", [dump(py, base)?, op])
+        }
+        Node::Pipe(a, b) => tagged(py, "pipe", [dump(py, a)?, dump(py, b)?]),
+        Node::MultiList(items) => tagged(py, "multi_list", [dump_all(items)?]),
+        Node::MultiHash(items) => {
+            let items = items
+                .iter()
+                .map(|(key, item)| PyTuple::new(py, [key.into_bound_py_any(py)?, dump(py, item)?]))
+                .collect::<PyResult<Vec<_>>>()?;
+            tagged(py, "multi_hash", [PyList::new(py, items)?.into_any()])
+        }
+    }
+}
+
+fn tagged<'py, const N: usize>(
+    py: Python<'py>,
+    name: &str,
+    fields: [Bound<'py, PyAny>; N],
+) -> PyResult<Bound<'py, PyAny>> {
+    let items = std::iter::once(name.into_bound_py_any(py)).chain(fields.into_iter().map(Ok));
+    Ok(PyTuple::new(py, items.collect::<PyResult<Vec<_>>>()?)?.into_any())
+}
+
+fn dump_slice<'py>(
+    py: Python<'py>,
+    start: &Option<isize>,
+    end: &Option<isize>,
+    step: &Option<isize>,
+) -> PyResult<Bound<'py, PyAny>> {
+    tagged(
+        py,
+        "slice",
+        [
+            start.into_bound_py_any(py)?,
+            end.into_bound_py_any(py)?,
+            step.into_bound_py_any(py)?,
+        ],
+    )
+}
+
+fn dump_list_op<'py>(py: Python<'py>, op: &ListOp) -> PyResult<Bound<'py, PyAny>> {
+    match op {
+        ListOp::Index(i) => tagged(py, "index", [i.into_bound_py_any(py)?]),
+        ListOp::Slice { start, end, step } => dump_slice(py, start, end, step),
+        ListOp::Length => tagged(py, "length", []),
+        ListOp::Reverse => tagged(py, "reverse", []),
+        ListOp::Flatten => tagged(py, "flatten", []),
+        ListOp::Contains(search) => tagged(py, "contains", [dump(py, search)?]),
+        ListOp::Filter(cond) => tagged(py, "filter", [dump(py, cond)?]),
+        ListOp::Map(key) => tagged(py, "map", [dump(py, key)?]),
+        ListOp::FilterMap(cond, key) => tagged(py, "filter_map", [dump(py, cond)?, dump(py, key)?]),
+        ListOp::Join(glue) => tagged(py, "join", [glue.into_bound_py_any(py)?]),
+        ListOp::Sort => tagged(py, "sort", []),
+        ListOp::Max => tagged(py, "max", []),
+        ListOp::Min => tagged(py, "min", []),
+        ListOp::Sum => tagged(py, "sum", []),
+        ListOp::Avg => tagged(py, "avg", []),
+        ListOp::SortBy(key) => tagged(py, "sort_by", [dump(py, key)?]),
+        ListOp::MinBy(key) => tagged(py, "min_by", [dump(py, key)?]),
+        ListOp::MaxBy(key) => tagged(py, "max_by", [dump(py, key)?]),
+        ListOp::Project(key) => tagged(py, "project", [dump(py, key)?]),
+    }
+}
+
+fn dump_str_op<'py>(py: Python<'py>, op: &StrOp) -> PyResult<Bound<'py, PyAny>> {
+    match op {
+        StrOp::Slice { start, end, step } => dump_slice(py, start, end, step),
+        StrOp::Reverse => tagged(py, "reverse", []),
+        StrOp::Contains(other) => tagged(py, "contains", [other.into_bound_py_any(py)?]),
+        StrOp::StartsWith(other) => tagged(py, "starts_with", [other.into_bound_py_any(py)?]),
+        StrOp::EndsWith(other) => tagged(py, "ends_with", [other.into_bound_py_any(py)?]),
+        StrOp::Length => tagged(py, "length", []),
+    }
+}
+
+/// A node written by `dump`, or its JSON array, read field by field.
+struct Tagged<'py> {
+    kind: &'static str,
+    name: String,
+    items: Bound<'py, PyAny>,
+    /// How deeply the node is nested in the expression, operations counting as their node.
+    depth: usize,
+    /// How many fields were read, which must end up being all of them.
+    read: Cell<usize>,
+}
+
+impl<'py> Tagged<'py> {
+    fn new(state: &Bound<'py, PyAny>, kind: &'static str, depth: usize) -> PyResult<Self> {
+        if depth > limits::MAX_EXPR_DEPTH {
+            return Err(InvalidArgumentError::new_err(format!(
+                "expression is nested more than {} levels deep",
+                limits::MAX_EXPR_DEPTH
+            )));
+        }
+        let is_array = state.is_instance_of::<PyTuple>() || state.is_instance_of::<PyList>();
+        let name = is_array
+            .then(|| {
+                state
+                    .get_item(0)
+                    .and_then(|name| name.extract::<String>())
+                    .ok()
+            })
+            .flatten();
+        let Some(name) = name else {
+            return Err(InvalidArgumentError::new_err(format!(
+                "expected a {}, got {}",
+                kind,
+                state.repr()?
+            )));
+        };
+        Ok(Self {
+            kind,
+            name,
+            items: state.clone(),
+            depth,
+            read: Cell::new(0),
+        })
+    }
+
+    /// An operation of this node, nested as deeply as it.
+    fn op(&self, i: usize, kind: &'static str) -> PyResult<Self> {
+        Self::new(&self.field(i)?, kind, self.depth)
+    }
+
+    fn field(&self, i: usize) -> PyResult<Bound<'py, PyAny>> {
+        self.read.set(self.read.get().max(i + 1));
+        self.items.get_item(i + 1).map_err(|_| {
+            InvalidArgumentError::new_err(format!(
+                "{} {:?} is missing field {}",
+                self.kind,
+                self.name,
+                i + 1
+            ))
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.items.len().unwrap_or(1).saturating_sub(1)
+    }
+
+    /// Refuses the fields left unread, which the node does not have.
+    fn finish<T>(&self, loaded: T) -> PyResult<T> {
+        if self.len() > self.read.get() {
+            return Err(InvalidArgumentError::new_err(format!(
+                "{} {:?} takes {} fields, got {}",
+                self.kind,
+                self.name,
+                self.read.get(),
+                self.len()
+            )));
+        }
+        Ok(loaded)
+    }
+
+    fn node(&self, i: usize) -> PyResult<Box<Node>> {
+        Ok(load_at(&self.field(i)?, self.depth + 1)?.into())
+    }
+
+    fn nodes(&self, i: usize) -> PyResult<Vec<Node>> {
+        self.field(i)?
+            .try_iter()?
+            .map(|item| load_at(&item?, self.depth + 1))
+            .collect()
+    }
+
+    fn extract<T: FromPyObjectOwned<'py>>(&self, i: usize) -> PyResult<T> {
+        let field = self.field(i)?;
+        field.extract().map_err(|_| {
+            InvalidArgumentError::new_err(format!(
+                "invalid field {} of {} {:?}: {}",
+                i + 1,
+                self.kind,
+                self.name,
+                field
+                    .repr()
+                    .map(|repr| repr.to_string())
+                    .unwrap_or_default()
+            ))
+        })
+    }
+
+    fn unknown(&self) -> PyErr {
+        InvalidArgumentError::new_err(format!("unknown {} {:?}", self.kind, self.name))
+    }
+}
+
+/// Rebuilds an expression from the output of `dump`.
+///
+/// Nodes nested deeper than `limits::MAX_EXPR_DEPTH` are refused, since loading them
+/// recurses, and so would everything done with the expression afterwards.
+pub fn load(state: &Bound<'_, PyAny>) -> PyResult<Node> {
+    load_at(state, 1)
+}
+
+fn load_at(state: &Bound<'_, PyAny>, depth: usize) -> PyResult<Node> {
+    let tagged = Tagged::new(state, "node", depth)?;
+    let node = match tagged.name.as_str() {
+        "this" => Node::This,
+        "literal" => Node::Literal(PyObjectWrapper::new(&tagged.field(0)?)),
+        "and" => Node::And(tagged.node(0)?, tagged.node(1)?),
+        "or" => Node::Or(tagged.node(0)?, tagged.node(1)?),
+        "not" => Node::Not(tagged.node(0)?),
+        "coalesce" => Node::Coalesce(tagged.nodes(0)?),
+        "merge" => Node::Merge(tagged.nodes(0)?),
+        "list" => Node::List(tagged.node(0)?, load_list_op(&tagged)?),
+        "str" => Node::Str(tagged.node(0)?, load_str_op(&tagged)?),
+        "struct" => Node::Struct(tagged.node(0)?, load_struct_op(&tagged)?),
+        "scalar" => Node::Scalar(tagged.node(0)?, load_scalar_op(&tagged)?),
+        "compare" => Node::Compare(tagged.node(0)?, load_comparison(&tagged)?),
+        "arithmetic" => Node::Arithmetic(tagged.node(0)?, load_arithmetic_op(&tagged)?),
+        "pipe" => Node::Pipe(tagged.node(0)?, tagged.node(1)?),
+        "multi_list" => Node::MultiList(tagged.nodes(0)?),
+        "multi_hash" => Node::MultiHash(load_multi_hash(&tagged)?),
+        _ => return Err(tagged.unknown()),
+    };
+    tagged.finish(node)
+}
+
+fn load_multi_hash(tagged: &Tagged<'_>) -> PyResult<Vec<(String, Node)>> {
+    tagged
+        .field(0)?
+        .try_iter()?
+        .map(|item| {
+            // Each entry reads like a node named after its key, with the expression as field.
+            let entry = Tagged::new(&item?, "multi_hash entry", tagged.depth)?;
+            let value = entry.node(0)?;
+            entry.finish((entry.name.clone(), *value))
+        })
+        .collect()
+}
+
+fn load_struct_op(tagged: &Tagged<'_>) -> PyResult<StructOp> {
+    let op = tagged.op(1, "struct operation")?;
+    let loaded = match op.name.as_str() {
+        "field" => StructOp::Field(op.extract(0)?),
+        "keys" => StructOp::Keys,
+        "values" => StructOp::Values,
+        _ => return Err(op.unknown()),
+    };
+    op.finish(loaded)
+}
+
+fn load_scalar_op(tagged: &Tagged<'_>) -> PyResult<ScalarOp> {
+    let op = tagged.op(1, "scalar operation")?;
+    let loaded = match op.name.as_str() {
+        "abs" => ScalarOp::Abs,
+        "ceil" => ScalarOp::Ceil,
+        "floor" => ScalarOp::Floor,
+        "neg" => ScalarOp::Neg,
+        _ => return Err(op.unknown()),
+    };
+    op.finish(loaded)
+}
+
+fn load_comparison(tagged: &Tagged<'_>) -> PyResult<ComparisonOp> {
+    let op = tagged.op(1, "comparison")?;
+    let loaded = match op.name.as_str() {
+        "eq" => ComparisonOp::Eq(op.node(0)?),
+        "ne" => ComparisonOp::Ne(op.node(0)?),
+        "lt" => ComparisonOp::Lt(op.node(0)?),
+        "le" => ComparisonOp::Le(op.node(0)?),
+        "gt" => ComparisonOp::Gt(op.node(0)?),
+        "ge" => ComparisonOp::Ge(op.node(0)?),
+        _ => return Err(op.unknown()),
+    };
+    op.finish(loaded)
+}
+
+fn load_arithmetic_op(tagged: &Tagged<'_>) -> PyResult<ArithmeticOp> {
+    let op = tagged.op(1, "arithmetic operation")?;
+    let loaded = match op.name.as_str() {
+        "add" => ArithmeticOp::Add(op.node(0)?),
+        "sub" => ArithmeticOp::Sub(op.node(0)?),
+        "mul" => ArithmeticOp::Mul(op.node(0)?),
+        "truediv" => ArithmeticOp::TrueDiv(op.node(0)?),
+        "floordiv" => ArithmeticOp::FloorDiv(op.node(0)?),
+        "mod" => ArithmeticOp::Mod(op.node(0)?),
+        "pow" => ArithmeticOp::Pow(op.node(0)?),
+        _ => return Err(op.unknown()),
+    };
+    op.finish(loaded)
+}
+
+fn load_list_op(tagged: &Tagged<'_>) -> PyResult<ListOp> {
+    let op = tagged.op(1, "list operation")?;
+    let loaded = match op.name.as_str() {
+        "index" => ListOp::Index(op.extract(0)?),
+        "slice" => ListOp::Slice {
+            start: op.extract(0)?,
+            end: op.extract(1)?,
+            step: op.extract(2)?,
+        },
+        "length" => ListOp::Length,
+        "reverse" => ListOp::Reverse,
+        "flatten" => ListOp::Flatten,
+        "contains" => ListOp::Contains(op.node(0)?),
+        "filter" => ListOp::Filter(op.node(0)?),
+        "map" => ListOp::Map(op.node(0)?),
+        "filter_map" => ListOp::FilterMap(op.node(0)?, op.node(1)?),
+        "join" => ListOp::Join(op.extract(0)?),
+        "sort" => ListOp::Sort,
+        "max" => ListOp::Max,
+        "min" => ListOp::Min,
+        "sum" => ListOp::Sum,
+        "avg" => ListOp::Avg,
+        "sort_by" => ListOp::SortBy(op.node(0)?),
+        "min_by" => ListOp::MinBy(op.node(0)?),
+        "max_by" => ListOp::MaxBy(op.node(0)?),
+        "project" => ListOp::Project(op.node(0)?),
+        _ => return Err(op.unknown()),
+    };
+    op.finish(loaded)
+}
+
+fn load_str_op(tagged: &Tagged<'_>) -> PyResult<StrOp> {
+    let op = tagged.op(1, "string operation")?;
+    let loaded = match op.name.as_str() {
+        "slice" => StrOp::Slice {
+            start: op.extract(0)?,
+            end: op.extract(1)?,
+            step: op.extract(2)?,
+        },
+        "reverse" => StrOp::Reverse,
+        "contains" => StrOp::Contains(op.extract(0)?),
+        "starts_with" => StrOp::StartsWith(op.extract(0)?),
+        "ends_with" => StrOp::EndsWith(op.extract(0)?),
+        "length" => StrOp::Length,
+        _ => return Err(op.unknown()),
+    };
+    op.finish(loaded)
+}
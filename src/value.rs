@@ -0,0 +1,273 @@
+use indexmap::IndexMap;
+use pyo3::exceptions::{PyOverflowError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::*;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+pub(crate) type Object = IndexMap<Arc<str>, Value>;
+
+/// How deeply Python containers may be nested to be converted, which keeps the conversion
+/// and every later walk over the document within the stack.
+const MAX_DEPTH: usize = 512;
+
+/// A JSON document held on the Rust side, cheap to clone since containers are shared.
+#[derive(Debug, Clone, Default)]
+pub(crate) enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(Arc<str>),
+    List(Arc<Vec<Value>>),
+    Object(Arc<Object>),
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Self::Null)
+    }
+
+    /// Booleans are not numbers, as in `eval::is_number`.
+    pub fn is_number(&self) -> bool {
+        matches!(self, Self::Int(_) | Self::Float(_))
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(i) => Some(*i as f64),
+            Self::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Self::Null => false,
+            Self::Bool(b) => *b,
+            Self::Int(i) => *i != 0,
+            Self::Float(f) => *f != 0.0,
+            Self::Str(s) => !s.is_empty(),
+            Self::List(items) => !items.is_empty(),
+            Self::Object(map) => !map.is_empty(),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "NoneType",
+            Self::Bool(_) => "bool",
+            Self::Int(_) => "int",
+            Self::Float(_) => "float",
+            Self::Str(_) => "str",
+            Self::List(_) => "list",
+            Self::Object(_) => "dict",
+        }
+    }
+
+    pub fn to_py<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        Ok(match self {
+            Self::Null => py.None().into_bound(py),
+            Self::Bool(b) => PyBool::new(py, *b).to_owned().into_any(),
+            Self::Int(i) => i.into_pyobject(py)?.into_any(),
+            Self::Float(f) => f.into_pyobject(py)?.into_any(),
+            Self::Str(s) => PyString::new(py, s).into_any(),
+            Self::List(items) => {
+                let output = PyList::empty(py);
+                for item in items.iter() {
+                    output.append(item.to_py(py)?)?;
+                }
+                output.into_any()
+            }
+            Self::Object(map) => {
+                let output = PyDict::new(py);
+                for (key, item) in map.iter() {
+                    output.set_item(key.as_ref(), item.to_py(py)?)?;
+                }
+                output.into_any()
+            }
+        })
+    }
+}
+
+/// Strict structural equality, telling `1`, `1.0` and `true` apart unlike the Python
+/// comparisons of `native`, while object keys may come in any order as in Python.
+/// Used to recognize identical literals.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Null, Self::Null) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::Float(a), Self::Float(b)) => a.to_bits() == b.to_bits(),
+            (Self::Str(a), Self::Str(b)) => a == b,
+            (Self::List(a), Self::List(b)) => a == b,
+            (Self::Object(a), Self::Object(b)) => {
+                a.len() == b.len() && a.iter().all(|(key, item)| b.get(key) == Some(item))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Null => {}
+            Self::Bool(b) => b.hash(state),
+            Self::Int(i) => i.hash(state),
+            Self::Float(f) => f.to_bits().hash(state),
+            Self::Str(s) => s.hash(state),
+            Self::List(items) => items.hash(state),
+            // The entries are hashed on their own and summed, so that their order does not matter.
+            Self::Object(map) => {
+                map.len().hash(state);
+                let entries = map.iter().fold(0u64, |sum, entry| {
+                    let mut hasher = Fnv::default();
+                    entry.hash(&mut hasher);
+                    sum.wrapping_add(hasher.finish())
+                });
+                state.write_u64(entries);
+            }
+        }
+    }
+}
+
+/// FNV-1a, a fixed hash function unlike the standard hasher, whose results therefore stay
+/// the same across processes.
+pub(crate) struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Looks up a key, scanning small objects instead of hashing the key.
+pub fn field<'a>(map: &'a Object, key: &str) -> Option<&'a Value> {
+    if map.len() <= 16 {
+        map.iter()
+            .find_map(|(name, value)| (name.as_ref() == key).then_some(value))
+    } else {
+        map.get(key)
+    }
+}
+
+/// Shares a single allocation between all the occurrences of an object key.
+#[derive(Default)]
+pub(crate) struct Interner(HashSet<Arc<str>>);
+
+impl Interner {
+    pub fn intern(&mut self, key: &str) -> Arc<str> {
+        if let Some(interned) = self.0.get(key) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(key);
+        self.0.insert(interned.clone());
+        interned
+    }
+
+    pub fn convert(&mut self, obj: &Bound<'_, PyAny>) -> PyResult<Value> {
+        self.convert_nested(obj, &mut Vec::new())
+    }
+
+    /// Keeps track of the containers being converted, to refuse the ones containing themselves.
+    fn enter(obj: &Bound<'_, PyAny>, parents: &mut Vec<usize>) -> PyResult<()> {
+        let id = obj.as_ptr() as usize;
+        if parents.contains(&id) {
+            return Err(PyValueError::new_err(
+                "document contains itself and cannot be converted to a native JSON value",
+            ));
+        }
+        if parents.len() == MAX_DEPTH {
+            return Err(PyValueError::new_err(format!(
+                "document is nested more than {} levels deep and cannot be converted to a native JSON value",
+                MAX_DEPTH
+            )));
+        }
+        parents.push(id);
+        Ok(())
+    }
+
+    fn convert_nested(
+        &mut self,
+        obj: &Bound<'_, PyAny>,
+        parents: &mut Vec<usize>,
+    ) -> PyResult<Value> {
+        if obj.is_none() {
+            return Ok(Value::Null);
+        }
+        if let Ok(b) = obj.cast::<PyBool>() {
+            return Ok(Value::Bool(b.is_true()));
+        }
+        if let Ok(int) = obj.cast::<PyInt>() {
+            return int.extract::<i64>().map(Value::Int).map_err(|_| {
+                PyOverflowError::new_err(format!(
+                    "integer {} does not fit in a native JSON value",
+                    int
+                ))
+            });
+        }
+        if let Ok(float) = obj.cast::<PyFloat>() {
+            return Ok(Value::Float(float.value()));
+        }
+        if let Ok(string) = obj.cast::<PyString>() {
+            return Ok(Value::Str(Arc::from(string.to_str()?)));
+        }
+        if let Ok(list) = obj.cast::<PyList>() {
+            Self::enter(obj, parents)?;
+            let items = list
+                .iter()
+                .map(|item| self.convert_nested(&item, parents))
+                .collect::<PyResult<_>>()?;
+            parents.pop();
+            return Ok(Value::List(Arc::new(items)));
+        }
+        if let Ok(dict) = obj.cast::<PyDict>() {
+            Self::enter(obj, parents)?;
+            let mut map = Object::with_capacity(dict.len());
+            for (key, item) in dict.iter() {
+                let key = key.cast::<PyString>().map_err(|_| {
+                    PyTypeError::new_err(format!(
+                        "object keys must be strings to build a native JSON value, not {}",
+                        key.get_type()
+                            .name()
+                            .map(|n| n.to_string())
+                            .unwrap_or_default()
+                    ))
+                })?;
+                map.insert(
+                    self.intern(key.to_str()?),
+                    self.convert_nested(&item, parents)?,
+                );
+            }
+            parents.pop();
+            return Ok(Value::Object(Arc::new(map)));
+        }
+        Err(PyTypeError::new_err(format!(
+            "{} is not a JSON type and cannot be converted to a native JSON value",
+            obj.get_type().name()?
+        )))
+    }
+}
+
+pub fn from_py(obj: &Bound<'_, PyAny>) -> PyResult<Value> {
+    Interner::default().convert(obj)
+}
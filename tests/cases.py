@@ -1,8 +1,15 @@
+import _thread
+import copy
+import json
 import math
+import pickle
 import statistics
+import tempfile
+import threading
 import time
-from collections.abc import Callable
+from collections.abc import Callable, Iterable
 from dataclasses import dataclass, field
+from pathlib import Path
 from typing import Any, NamedTuple, Self
 
 import dictexprs as dx
@@ -11,6 +18,7 @@ import jmespath
 from tests.data import DataBase
 
 type TestFn = Callable[[], Any]
+type Expected = Callable[[DataBase], Any]
 
 
 class BenchmarkResult(NamedTuple):
@@ -41,6 +49,8 @@ def _get_perf(func: TestFn, runs: int) -> float:
 class Case:
     dx_query: dx.Expr
     jmes_query: str
+    rendered: bool = False
+    """Whether `jmes_query` was rendered from `dx_query`, with type guards `dx.parse` rejects."""
 
     def check(self, data: DataBase) -> None:
         """Checks the query against the provided data."""
@@ -64,6 +74,11 @@ class Case:
         assert _check_equal(dx_result, jmes_result), print(
             f"Query: {self.jmes_query!r}\n  Got:   {dx_result!r}\n  Want:  {jmes_result!r}"
         )
+        check_native(self.dx_query, data)
+        if not self.rendered:
+            check_parsed(self.jmes_query, data)
+        check_rendered(self.dx_query, data)
+        check_rendered(self.dx_query.optimize(), data)
         print(f"✔ {self.jmes_query}")
 
     def warmup(self, data: DataBase, compiled: Any) -> None:
@@ -84,18 +99,620 @@ class Case:
         )
 
 
+def check_native(dx_query: dx.Expr, data: DataBase) -> None:
+    """Checks that evaluating on native data agrees with evaluating on Python objects."""
+    dx_result = dx.DataJson(data).query(dx_query).collect()
+    native_result = dx.DataJson(data, native=True).query(dx_query).collect()
+    assert _check_equal(native_result, dx_result), print(
+        f"Native: {dx_query!r}\n  Got:   {native_result!r}\n  Want:  {dx_result!r}"
+    )
+    parsed_result = dx.DataJson.from_json(json.dumps(data)).query(dx_query).collect()
+    assert _check_equal(parsed_result, dx_result), print(
+        f"From JSON: {dx_query!r}\n  Got:   {parsed_result!r}\n  Want:  {dx_result!r}"
+    )
+    for parallel in (False, True):
+        batch_result = dx_query.search_many([data, {}, data], parallel=parallel)
+        assert _check_equal(batch_result[0], dx_result), print(
+            f"Batch: {dx_query!r}\n  Got:   {batch_result!r}\n  Want:  {dx_result!r}"
+        )
+        assert _check_equal(batch_result[2], dx_result)
+    with tempfile.TemporaryDirectory() as tmp:
+        path = Path(tmp, "data.json")
+        path.write_text(json.dumps(data))
+        scanned_result = dx.scan_json(path).query(dx_query).collect()
+    assert _check_equal(scanned_result, dx_result), print(
+        f"Scanned: {dx_query!r}\n  Got:   {scanned_result!r}\n  Want:  {dx_result!r}"
+    )
+
+
+def check_parsed(jmes_query: str, data: DataBase) -> None:
+    """Checks that the parsed query string agrees with the reference implementation."""
+    check_native(dx.parse(jmes_query), data)
+    dx_result = dx.DataJson(data).query(dx.parse(jmes_query)).collect()
+    jmes_result = jmespath.search(jmes_query, data)
+    assert _check_equal(dx_result, jmes_result), print(
+        f"Parsed: {jmes_query!r}\n  Got:   {dx_result!r}\n  Want:  {jmes_result!r}"
+    )
+
+
+def check_sort_mixed() -> None:
+    """Checks that keys of mixed types sort strings first, then numbers, then the rest."""
+    items = dx.lit(
+        [{"k": 3}, {"k": "b"}, {"k": None}, {"k": 1.5}, {"k": "a"}, {"k": True}]
+    )
+    key = dx.field("k")
+    sorted_keys = items.list.sort_by(key).list.map(key)
+    assert dx.DataJson({}).query(sorted_keys).collect() == ["a", "b", True, 1.5, 3, None]
+    assert dx.DataJson({}).query(items.list.min_by(key)).collect() == {"k": "a"}
+    assert dx.DataJson({}).query(items.list.max_by(key)).collect() == {"k": None}
+    print("✔ sort_by with mixed key types")
+
+
+def check_rendered(dx_query: dx.Expr, data: DataBase) -> None:
+    """Checks that the rendered JMESPath string agrees with the expression it came from."""
+    rendered = dx_query.to_jmespath()
+    dx_result = dx.DataJson(data).query(dx_query).collect()
+    jmes_result = jmespath.search(rendered, data)
+    assert _check_equal(dx_result, jmes_result), print(
+        f"Rendered: {rendered!r}\n  Got:   {dx_result!r}\n  Want:  {jmes_result!r}"
+    )
+
+
+def check_rendered_nulls() -> None:
+    """Checks rendered flattens on null items, which a `[]` projection alone would drop."""
+    data = {"xs": [[1, None], None, 2, [], [[None]], {"a": None}, [False, {"a": 1}]]}
+    queries = [
+        dx.field("xs").list.flatten(),
+        dx.field("xs").list.flatten().list.flatten(),
+        dx.field("xs").list.flatten().list.map(dx.field("a")),
+        dx.field("xs").list.flatten().list.length(),
+        dx.field("missing").list.flatten(),
+        dx.parse("xs[]"),
+        dx.parse("xs[].a"),
+    ]
+    for query in queries:
+        check_rendered(query, data)
+    try:
+        dx.field("s").str.slice(1).to_jmespath()
+    except dx.InvalidArgumentError as exc:
+        assert "no JMESPath equivalent" in str(exc), print(exc)
+    else:
+        raise AssertionError("str.slice was rendered")
+    print("✔ to_jmespath with nulls")
+
+def check_expected(dx_query: dx.Expr, expected: Expected, data: DataBase) -> None:
+    """Checks a query without JMESPath equivalent against a plain Python computation."""
+    check_native(dx_query, data)
+    dx_result = dx.DataJson(data).query(dx_query).collect()
+    want = expected(data)
+    assert _check_equal(dx_result, want), print(
+        f"Query: {dx_query!r}\n  Got:   {dx_result!r}\n  Want:  {want!r}"
+    )
+
+
+def check_ndjson(data: DataBase) -> None:
+    """Scans the users as NDJSON lines, with a blank and a malformed line mixed in."""
+    query = dx.field("age") * 2
+    want = [dx.DataJson(user).query(query).collect() for user in data["users"]]
+    lines = [json.dumps(user) for user in data["users"]]
+    lines[1:1] = ["", "{not json"]
+    with tempfile.TemporaryDirectory() as tmp:
+        path = Path(tmp, "users.ndjson")
+        path.write_text("\n".join(lines))
+        skipped = dx.scan_ndjson(path, on_error="skip").query(query)
+        assert skipped.collect() == want
+        assert list(skipped) == want
+        nulled = dx.scan_ndjson(path, on_error="null").query(query).collect()
+        assert nulled == [want[0], None, *want[1:]]
+        try:
+            dx.scan_ndjson(path).query(query).collect()
+        except ValueError as exc:
+            assert "line 3" in str(exc)
+        else:
+            raise AssertionError("malformed line was not reported")
+        path.write_text('{"n": 9223372036854775808}\n{"n": 18446744073709551616}\n')
+        for on_error in ("raise", "skip", "null"):
+            scanned = dx.scan_ndjson(path, on_error=on_error).query(dx.field("n")).collect()
+            assert scanned == [2.0**63, 2.0**64], print(on_error, scanned)
+    assert dx.DataJson.from_json('[9223372036854775808]').query(dx.element()).collect() == [2.0**63]
+    print("✔ scan_ndjson")
+
+
+def check_streamed(data: DataBase) -> None:
+    """Streams the users array item by item and compares with the collected result."""
+    queries = [
+        dx.element().list.filter(dx.field("age") > 30),
+        dx.element().list.map(dx.field("address").struct.field("city")),
+        dx.element().list.filter(dx.field("active")).list.map(dx.field("name")),
+        dx.parse("[?age < `40`].category[0]"),
+    ]
+    with tempfile.TemporaryDirectory() as tmp:
+        path = Path(tmp, "users.json")
+        path.write_text(json.dumps(data["users"], indent=2))
+        for query in queries:
+            want = dx.DataJson(data["users"]).query(query).collect()
+            streamed = list(dx.scan_json(path).query(query))
+            assert streamed == want, print(
+                f"Streamed: {query!r}\n  Got:   {streamed!r}\n  Want:  {want!r}"
+            )
+    print("✔ scan_json streaming")
+
+
+def check_search_many() -> None:
+    """Checks that parallel searches agree with sequential ones on documents that are not plain JSON."""
+    cyclic: list[object] = []
+    cyclic.append(cyclic)
+    docs = [
+        {"n": 2**70, "tags": ("a", "b")},
+        {"n": 1, "tags": ["a", "b"]},
+        {"n": 1.5, "tags": cyclic},
+        {"n": 3, "tags": {1: "a"}},
+        {"n": 4, "tags": math.pi},
+        {"n": 2**63 - 1, "tags": []},
+        {"n": -(2**63), "tags": []},
+    ]
+    queries = [
+        dx.field("n") + 1,
+        dx.field("n") * 3,
+        -dx.field("n"),
+        dx.field("n") // -1,
+        dx.field("n") ** 2,
+        dx.field("tags").list.length(),
+        dx.field("tags") == dx.lit(("a", "b")),
+        dx.field("n").eq(dx.lit(2**70)),
+    ]
+    for query in queries:
+        want = query.search_many(docs)
+        got = query.search_many(docs, parallel=True)
+        assert got == want, print(f"Parallel: {query!r}\n  Got:   {got!r}\n  Want:  {want!r}")
+    tags = ["a"]
+    same_tags = dx.field("tags") == dx.lit(tags)
+    tags.append("b")
+    doc = {"tags": ["a"]}
+    assert dx.DataJson(doc).query(same_tags).collect() is True
+    assert dx.DataJson(doc, native=True).query(same_tags).collect() is True
+    assert same_tags.search_many([doc] * 4) == [True] * 4
+    assert same_tags.search_many([doc] * 4, parallel=True) == [True] * 4
+    print("✔ search_many")
+
+
+def check_overflow() -> None:
+    """Checks that integer arithmetic overflowing 64 bits stays exact on native documents."""
+    doc = {"n": 2**62 + 1}
+    doubled = dx.field("n") + dx.field("n")
+    for native in (False, True):
+        db = dx.DataJson(doc, native=native)
+        assert db.query(doubled).collect() == 2**63 + 2
+        assert db.select(doubled=doubled, n=dx.field("n")) == {"doubled": 2**63 + 2, "n": 2**62 + 1}
+    with tempfile.TemporaryDirectory() as tmp:
+        path = Path(tmp, "doc.ndjson")
+        path.write_text(json.dumps(doc))
+        try:
+            dx.scan_ndjson(path).query(doubled).collect()
+        except dx.InvalidArgumentError as exc:
+            assert "overflows 64 bits" in str(exc), print(exc)
+        else:
+            raise AssertionError("scanned arithmetic overflow was not reported")
+    print("✔ integer overflow")
+
+def check_select(cases: Iterable[Case], data: DataBase) -> None:
+    """Selects every case at once, sharing their common subexpressions."""
+    named = {f"case_{i}": case.dx_query for i, case in enumerate(cases)}
+    for native in (False, True):
+        db = dx.DataJson(data, native=native)
+        selected = db.select(**named)
+        assert list(selected) == list(named)
+        for name, query in named.items():
+            want = db.query(query).collect()
+            assert _check_equal(selected[name], want), print(
+                f"Select: {query!r}\n  Got:   {selected[name]!r}\n  Want:  {want!r}"
+            )
+    print("✔ DataJson.select")
+
+
+def check_explained() -> None:
+    """Checks the single-line and tree renderings of an expression, before and after optimization."""
+    query = (
+        dx.field("users")
+        .list.filter(dx.field("age") > 30)
+        .list.map(dx.field("name"))
+        .list.slice(1, None, 2)
+    )
+    assert repr(query) == (
+        'field("users").list.filter(field("age").gt(lit(30)))'
+        '.list.map(field("name")).list.slice(1, None, 2)'
+    ), print(repr(query))
+    assert query.explain() == "\n".join(
+        [
+            "list.slice(1, None, 2)",
+            "  list.map",
+            "    list.filter",
+            '      field("users")',
+            "      cond: gt",
+            '        field("age")',
+            "        other: lit(30)",
+            '    expr: field("name")',
+        ]
+    ), print(query.explain())
+    assert query.explain(optimized=True) == "\n".join(
+        [
+            "list.slice(1, None, 2)",
+            "  list.filter_map",
+            '    field("users")',
+            "    cond: gt",
+            '      field("age")',
+            "      other: lit(30)",
+            '    expr: field("name")',
+        ]
+    ), print(query.explain(optimized=True))
+    assert repr(query.optimize()) == repr(query), print(repr(query.optimize()))
+    folded = ((dx.lit(1) + dx.lit(2)) * dx.lit(3) > dx.lit(8)).not_()
+    assert repr(folded.optimize()) == "lit(false)", print(repr(folded.optimize()))
+    projected = dx.parse("users[*].name")
+    assert repr(projected) == (
+        'field("users").list.map(field("name")).list.filter(element().ne(lit(null)))'
+    ), print(repr(projected))
+    assert repr(dx.lit({"a": [1, None]})) == 'lit({"a":[1,null]})'
+    print("✔ Expr.explain")
+
+
+def check_traced(cases: Iterable[Case], data: DataBase) -> None:
+    """Checks that tracing agrees with collecting, and flags the node that broke a chain."""
+    for case in cases:
+        for native in (False, True):
+            query = dx.DataJson(data, native=native).query(case.dx_query)
+            root = query.trace()[0]
+            assert root["depth"] == 0 and root["index"] is None
+            assert (root["output"] == "None") == (query.collect() is None), print(
+                f"Trace: {case.dx_query!r}\n  Got:   {root!r}"
+            )
+    users = {"users": [{"address": {"city": "Paris"}}, {"address": "unknown"}, {}]}
+    query = dx.field("users").list.map(dx.field("address").struct.field("city"))
+    broken = [entry for entry in dx.DataJson(users).query(query).trace() if entry["mismatch"]]
+    assert broken == [
+        {
+            "expr": 'field("address").struct.field("city")',
+            "depth": 1,
+            "index": 1,
+            "path": "$.users[1]",
+            "input": "object",
+            "output": "None",
+            "mismatch": True,
+        }
+    ], print(broken)
+    print("✔ LazyQuery.trace")
+
+
+def check_strict(cases: Iterable[Case], data: DataBase) -> None:
+    """Checks that strict mode either agrees with the default one, or explains its nulls."""
+    for case in cases:
+        for native in (False, True):
+            query = dx.DataJson(data, native=native).query(case.dx_query)
+            want = query.collect()
+            try:
+                got = query.collect(strict=True)
+            except dx.TypeMismatchError as exc:
+                assert exc.path is not None and exc.expected != exc.actual, print(exc)
+                continue
+            assert _check_equal(got, want), print(
+                f"Strict: {case.dx_query!r}\n  Got:   {got!r}\n  Want:  {want!r}"
+            )
+    data = {"users": [{"age": 30}, {"age": "thirty"}]}
+    query = dx.DataJson(data).query(dx.field("users").list.map(dx.field("age")).list.sum())
+    assert query.collect() is None
+    try:
+        query.collect(strict=True)
+    except dx.TypeMismatchError as exc:
+        assert (exc.path, exc.expected, exc.actual) == ("$.users[1].age", "number", "string")
+        assert exc.expr == 'field("users").list.map(field("age")).list.sum()'
+    else:
+        raise AssertionError("strict mode should raise on a string age")
+    folded = dx.DataJson({}).query(dx.lit("x").list.length())
+    assert folded.collect() is None
+    try:
+        folded.collect(strict=True)
+    except dx.TypeMismatchError as exc:
+        assert (exc.expected, exc.actual) == ("array", "string"), print(exc)
+    else:
+        raise AssertionError("strict mode should raise on a folded mismatch")
+    print("✔ strict collect")
+
+
+def check_errors() -> None:
+    """Checks that failures raise query errors locating the node and the value that failed."""
+    assert issubclass(dx.QueryError, ValueError)
+    for error in (
+        dx.ParseError,
+        dx.TypeMismatchError,
+        dx.InvalidArgumentError,
+        dx.EvaluationLimitError,
+    ):
+        assert issubclass(error, dx.QueryError)
+    try:
+        dx.parse("users[")
+    except dx.ParseError as exc:
+        assert exc.expr == "users[" and exc.path is None
+    else:
+        raise AssertionError("invalid query was parsed")
+    nested = ["!" * 10_000 + "a", "(" * 10_000 + "a" + ")" * 10_000, "[" * 10_000]
+    for query in [*nested, "!" * 1_000 + "a", ".".join(["a"] * 1_000)]:
+        try:
+            dx.parse(query)
+        except dx.EvaluationLimitError as exc:
+            assert "max_expr_depth of 1000" in str(exc), print(exc)
+        else:
+            raise AssertionError("deeply nested query was parsed")
+    assert dx.DataJson({"a": 1}).query(dx.parse("!" * 998 + "a")).collect() is True
+    data = {"tags": [1, "a"], "scores": {"all": [1, 2]}}
+    failing = [
+        (dx.field("tags").list.sort(), dx.TypeMismatchError, "$.tags"),
+        (
+            dx.field("scores").struct.field("all").list.slice(None, None, 0),
+            dx.InvalidArgumentError,
+            "$.scores.all",
+        ),
+    ]
+    for query, error, path in failing:
+        for native in (False, True):
+            try:
+                dx.DataJson(data, native=native).query(query).collect()
+            except dx.QueryError as exc:
+                assert type(exc) is error, print(repr(exc))
+                assert (exc.expr, exc.path) == (repr(query), path), print(exc.expr, exc.path)
+            else:
+                raise AssertionError(f"{query!r} did not fail")
+    huge = dx.field("n") ** 10**9
+    with tempfile.TemporaryDirectory() as tmp:
+        path = Path(tmp, "doc.ndjson")
+        path.write_text(json.dumps({"n": 2}))
+        powers: list[Callable[[], object]] = [
+            lambda: dx.DataJson({"n": 2}).query(huge).collect(),
+            lambda: dx.DataJson({"n": 2}, native=True).query(huge).collect(),
+            lambda: huge.search_many([{"n": 2}], parallel=True),
+            lambda: dx.scan_ndjson(path).query(huge).collect(),
+        ]
+        for power in powers:
+            try:
+                power()
+            except dx.InvalidArgumentError as exc:
+                assert "limited to 65536 bits" in str(exc), print(exc)
+                assert exc.expr == 'field("n").pow(lit(1000000000))', print(exc.expr)
+            else:
+                raise AssertionError("huge integer power was computed")
+    cyclic: list[object] = []
+    cyclic.append(cyclic)
+    nested: list[object] = []
+    for _ in range(300_000):
+        nested = [nested]
+    for document in (cyclic, nested):
+        try:
+            dx.DataJson(document, native=True)
+        except ValueError:
+            pass
+        else:
+            raise AssertionError("unconvertible document was converted")
+    shared = [1, 2]
+    native = dx.DataJson({"a": shared, "b": [shared, shared]}, native=True)
+    assert native.query(dx.field("b").list.flatten()).collect() == [1, 2, 1, 2]
+    print("✔ query errors")
+
+
+def check_limits(cases: Iterable[Case], data: DataBase) -> None:
+    """Checks that generous limits change nothing, and that tight ones abort cleanly."""
+    generous = {
+        "max_expr_depth": 1_000,
+        "max_data_depth": 1_000,
+        "max_evaluations": 10_000_000,
+        "max_output": 10_000_000,
+    }
+    for case in cases:
+        for native in (False, True):
+            query = dx.DataJson(data, native=native).query(case.dx_query)
+            got = query.collect(**generous)
+            want = query.collect()
+            assert _check_equal(got, want), print(
+                f"Limits: {case.dx_query!r}\n  Got:   {got!r}\n  Want:  {want!r}"
+            )
+    data = {"xs": [{"ys": list(range(10))} for _ in range(10)], "deep": [[[1]]]}
+    expr = dx.field("xs").list.map(dx.field("ys").list.map(dx.element().add(dx.lit(1))))
+    constant = dx.lit(list(range(100))).list.map(dx.element().add(dx.lit(1))).list.sum()
+    tight = [
+        (expr, {"max_expr_depth": 3}),
+        (dx.field("deep"), {"max_data_depth": 2}),
+        (expr, {"max_evaluations": 50}),
+        (constant, {"max_evaluations": 50}),
+        (expr, {"max_output": 50}),
+    ]
+    for query, limits in tight:
+        for native in (False, True):
+            try:
+                dx.DataJson(data, native=native).query(query).collect(**limits)
+            except dx.EvaluationLimitError as exc:
+                assert next(iter(limits)) in str(exc), print(exc)
+            else:
+                raise AssertionError(f"{query!r} did not go over {limits}")
+    deep = dx.field("a")
+    for _ in range(2_000):
+        deep = deep.not_()
+    attempts: list[Callable[[], object]] = [
+        lambda: dx.DataJson({"a": 1}).query(deep),
+        lambda: dx.DataJson({"a": 1}).query(dx.parse("a" + ".a" * 2_000)),
+        lambda: deep.search_many([{"a": 1}], parallel=True),
+        lambda: deep.optimize(),
+    ]
+    for attempt in attempts:
+        try:
+            attempt()
+        except dx.EvaluationLimitError as exc:
+            assert "max_expr_depth of 1000" in str(exc), print(exc)
+        else:
+            raise AssertionError("deeply nested expression was optimized")
+    print("✔ evaluation limits")
+
+
+def check_timeout() -> None:
+    """Checks that long queries stop once their timeout has passed, or when interrupted."""
+    data = {"xs": list(range(10_000))}
+    query = dx.field("xs").list.map(dx.element().add(dx.lit(1)))
+    for native in (False, True):
+        collected = dx.DataJson(data, native=native).query(query)
+        assert collected.collect(timeout=60) == collected.collect()
+        for kwargs in ({}, {"strict": True}):
+            try:
+                collected.collect(timeout=1e-6, **kwargs)
+            except TimeoutError:
+                pass
+            else:
+                raise AssertionError("query did not time out")
+    for timeout in (0, -1, float("inf"), float("nan")):
+        try:
+            dx.DataJson(data).query(query).collect(timeout=timeout)
+        except dx.InvalidArgumentError:
+            pass
+        else:
+            raise AssertionError(f"timeout of {timeout} was accepted")
+    haystack = list(range(20_000))
+    slow = dx.field("xs").list.map(dx.lit(haystack).list.contains(dx.element()))
+    docs = [{"xs": haystack}] * 64
+    with tempfile.TemporaryDirectory() as tmp:
+        path = Path(tmp, "docs.ndjson")
+        path.write_text("\n".join(json.dumps(doc) for doc in docs))
+        attempts: list[Callable[[], object]] = [
+            lambda: slow.search_many(docs, parallel=True),
+            lambda: dx.scan_ndjson(path).query(slow).collect(),
+        ]
+        for attempt in attempts:
+            timer = threading.Timer(0.1, _thread.interrupt_main)
+            timer.start()
+            start = time.perf_counter()
+            try:
+                attempt()
+            except KeyboardInterrupt:
+                assert time.perf_counter() - start < 1, print(time.perf_counter() - start)
+            else:
+                raise AssertionError("query was not interrupted")
+            finally:
+                timer.cancel()
+    print("✔ timeout")
+
+
+def check_pickled(cases: Iterable[Case], data: DataBase) -> None:
+    """Checks that pickled and copied expressions and queries evaluate the same."""
+    for case in cases:
+        expr = case.dx_query
+        for other in (pickle.loads(pickle.dumps(expr)), copy.copy(expr), copy.deepcopy(expr)):
+            assert repr(other) == repr(expr), print(f"Pickled: {other!r} != {expr!r}")
+        for native in (False, True):
+            query = dx.DataJson(data, native=native).query(expr)
+            want = query.collect()
+            for other in (
+                pickle.loads(pickle.dumps(query)),
+                copy.copy(query),
+                copy.deepcopy(query),
+            ):
+                assert _check_equal(other.collect(), want), print(f"Pickled: {expr!r}")
+    values = [1, 2]
+    copied = copy.deepcopy(dx.lit(values))
+    values.append(3)
+    assert repr(copied) == "lit([1,2])"
+    print("✔ pickling")
+
+
+def check_json_ast(cases: Iterable[Case], data: DataBase) -> None:
+    """Checks that expressions go through their JSON AST unchanged."""
+    for case in cases:
+        ast = case.dx_query.to_json_ast()
+        loaded = dx.Expr.from_json_ast(ast)
+        assert repr(loaded) == repr(case.dx_query), print(f"JSON AST: {ast}")
+        assert loaded.to_json_ast() == ast
+        got = dx.DataJson(data).query(loaded).collect()
+        want = dx.DataJson(data).query(case.dx_query).collect()
+        assert _check_equal(got, want), print(f"JSON AST: {ast}")
+    ast = json.loads(dx.field("a").to_json_ast())
+    assert ast == {"version": 1, "expr": ["struct", ["this"], ["field", "a"]]}
+    for invalid in (
+        {"version": 2, "expr": ["this"]},
+        {"expr": ["this"]},
+        {"version": 1, "expr": ["unknown"]},
+        {"version": 1, "expr": ["list", ["this"]]},
+        {"version": 1, "expr": ["this", ["this"]]},
+        {"version": 1, "expr": ["list", ["this"], ["index", "first"]]},
+        {"version": 1, "expr": ["multi_hash", [["a"]]]},
+    ):
+        try:
+            dx.Expr.from_json_ast(json.dumps(invalid))
+        except dx.InvalidArgumentError:
+            pass
+        else:
+            raise AssertionError(f"{invalid} was loaded")
+    nested: tuple[object, ...] = ("this",)
+    for _ in range(10_000):
+        nested = ("not", nested)
+    try:
+        dx.field("a").__setstate__((1, nested))
+    except dx.InvalidArgumentError:
+        pass
+    else:
+        raise AssertionError("deeply nested state was loaded")
+    try:
+        dx.lit(object()).to_json_ast()
+    except dx.InvalidArgumentError:
+        pass
+    else:
+        raise AssertionError("a literal that is not JSON was written")
+    print("✔ JSON AST")
+
+
+def check_meta(cases: Iterable[Case]) -> None:
+    """Checks that copies of an expression are equal to it, and other expressions are not."""
+    exprs = [case.dx_query for case in cases]
+    for expr in exprs:
+        for other in (pickle.loads(pickle.dumps(expr)), dx.Expr.from_json_ast(expr.to_json_ast())):
+            assert expr.meta.eq(other) and expr.meta.hash() == other.meta.hash(), print(
+                f"Meta: {expr!r}"
+            )
+    distinct = {expr.meta.hash() for expr in exprs}
+    assert len(distinct) == len({repr(expr) for expr in exprs})
+    assert dx.lit([1, {"a": None}]).meta.eq(dx.lit([1, {"a": None}]))
+    assert not dx.lit(1).meta.eq(dx.lit(1.0))
+    assert not dx.field("a").meta.eq(dx.field("b"))
+    ordered = dx.lit({"a": 1, "b": [{"c": 2, "d": 3}]})
+    reordered = dx.lit({"b": [{"d": 3, "c": 2}], "a": 1})
+    assert ordered.meta.eq(reordered) and ordered.meta.hash() == reordered.meta.hash()
+    assert not dx.lit(True).meta.eq(dx.lit(1))
+    big = dx.lit(2**70)
+    assert big.meta.eq(dx.lit(2**70)) and big.meta.hash() == dx.lit(2**70).meta.hash()
+    assert not big.meta.eq(dx.lit(2**70 + 1)) and not big.meta.eq(dx.lit(float(2**70)))
+    assert dx.field("a").meta.hash() == 0xFEBBC29339F0B478, "meta.hash must not change"
+    print("✔ structural equality")
+
+
 @dataclass(slots=True)
 class CasesBuilder:
     cases: list[Case] = field(default_factory=list[Case])
 
-    def add(self, dx_query: dx.Expr, jmes_query: str) -> Self:
-        self.cases.append(Case(dx_query, jmes_query))
+    def add(self, dx_query: dx.Expr, jmes_query: str | None = None) -> Self:
+        """Adds a case, rendering the reference query from `dx_query` when omitted."""
+        if jmes_query is None:
+            self.cases.append(Case(dx_query, dx_query.to_jmespath(), rendered=True))
+        else:
+            self.cases.append(Case(dx_query, jmes_query))
         return self
 
     def get(self) -> list[Case]:
         return self.cases
 
 
+def _projected(base: dx.Expr, rhs: dx.Expr) -> dx.Expr:
+    """Builds the `base[*].rhs` projection of `dx.parse`, which has no Python API."""
+
+    def ast(expr: dx.Expr) -> Any:
+        return json.loads(expr.to_json_ast())["expr"]
+
+    projected = ["list", ast(base), ["project", ast(rhs)]]
+    return dx.Expr.from_json_ast(json.dumps({"version": 1, "expr": projected}))
+
+
 def build_cases() -> list[Case]:
     users = dx.field("users")
     return (
@@ -230,5 +847,169 @@ def build_cases() -> list[Case]:
             users.list.map(dx.field("age")).list.sum().abs(),
             "abs(sum(users[*].age))",
         )
+        .add(
+            users.list.get(0).pipe(dx.merge(dx.element(), dx.lit({"extra_field": 1}))),
+            'users[0] | merge(@, `{"extra_field":1}`)',
+        )
+        .add(
+            users.list.get(0).pipe(dx.coalesce(dx.field("MISSING"), dx.field("name"))),
+            "users[0] | not_null(MISSING, name)",
+        )
+        .add(
+            users.list.get(-1).pipe(dx.field("address")).pipe(dx.struct().keys()),
+        )
+        .add(
+            users.list.map(
+                dx.struct_from(
+                    name=dx.field("name"),
+                    city=dx.field("address").struct.field("city"),
+                )
+            ),
+            "users[*].{name: name, city: address.city}",
+        )
+        .add(
+            users.list.map(
+                dx.struct_from(
+                    {"first name": dx.field("name"), "tier": "VIP"},
+                    adult=dx.field("age").ge(18),
+                )
+            ),
+        )
+        .add(
+            users.list.map(dx.concat_list(dx.field("name"), dx.field("age"))),
+            "users[*].[name, age]",
+        )
+        .add(
+            dx.concat_list(users.list.length(), users.list.get(0).pipe(dx.field("name")), 1),
+        )
+        .add(
+            users.list.filter(
+                (dx.field("age") >= 30) & ~(dx.field("active") == False)  # noqa: E712
+            ).list.map(dx.element()["name"]),
+        )
+        .add(users[0]["address"]["city"], "users[0].address.city")
+        .add(
+            users[1:4].list.map((dx.field("age") < 40) | (dx.field("age") != 50)),
+        )
+        .add(users[::-1].list.map(dx.field("id")), "users[::-1].id")
+        .add(users.list.map(dx.field("name").list.length()))
+        .add(users.list.map(dx.field("name").list.reverse()))
+        .add(users.list.map(dx.field("name").list.contains("a")))
+        .add(users.list.map(dx.field("category").list.contains(dx.field("name"))))
+        .add(users.list.map(dx.field("address").str.length()))
+        .add(users.list.map(dx.field("category").str.reverse()))
+        .add(users.list.map(dx.field("category").str.contains("VIP")))
+        .add(dx.field("tags").list.reverse())
+        .add(_projected(users, dx.field("age") > 30))
+        .add(_projected(users, dx.field("category").list.length()))
         .get()
     )
+
+
+PARSE_ONLY_CASES: list[str] = [
+    "users[*].[name, age]",
+    "users[*].{n: name, city: address.city}",
+    "users[-1].name",
+    "users[::-1].id",
+    "users[5:0:-2].id",
+    "users[:2:-3].id",
+    "users[1:10:2].id",
+    "tags.*",
+    "users[0].address.*",
+    "users[?active].name | [0]",
+    "users[0].nested_scores[*][0]",
+    "{first: users[0].name, count: length(users)}",
+    "[users[0].name, `1`, 'raw']",
+    "users[*].name | sort(@) | reverse(@)",
+    "length(tags)",
+    "users[?contains(name, 'o')].name",
+    "users[*].category[?@ == 'VIP']",
+    "(users[*].name)[0]",
+    "sales[0][?shipped].order_id",
+    "missing[*].x",
+]
+
+
+def build_expected_cases() -> list[tuple[dx.Expr, Expected]]:
+    users = dx.field("users")
+    products = dx.field("products")
+    return [
+        (
+            products.list.map(dx.field("price") * 2 + 1),
+            lambda data: [p["price"] * 2 + 1 for p in data["products"]],
+        ),
+        (
+            users.list.map(100 - dx.field("age")),
+            lambda data: [100 - u["age"] for u in data["users"]],
+        ),
+        (
+            users.list.map(dx.field("age") // 10 % 3),
+            lambda data: [u["age"] // 10 % 3 for u in data["users"]],
+        ),
+        (
+            users.list.map(dx.field("age") / dx.field("id")),
+            lambda data: [u["age"] / u["id"] for u in data["users"]],
+        ),
+        (
+            users.list.map(-dx.field("age") ** 2),
+            lambda data: [-(u["age"] ** 2) for u in data["users"]],
+        ),
+        (
+            dx.field("sales").list.flatten().list.map(
+                dx.field("amount").sub(dx.field("items").mul(0.5))
+            ),
+            lambda data: [
+                s["amount"] - s["items"] * 0.5 for day in data["sales"] for s in day
+            ],
+        ),
+        (
+            users.list.map(dx.field("name") + 1),
+            lambda data: [None for _ in data["users"]],
+        ),
+        (
+            users.list.map(dx.field("active") * 2),
+            lambda data: [None for _ in data["users"]],
+        ),
+        (
+            users.list.map(dx.field("age") % 0),
+            lambda data: [None for _ in data["users"]],
+        ),
+        (
+            dx.lit(2) + dx.lit(3) * dx.lit(4),
+            lambda data: 14,
+        ),
+        (
+            dx.lit(True).and_(users.list.length()),
+            lambda data: len(data["users"]),
+        ),
+        (
+            users.list.length().gt(3).and_(dx.lit(True)),
+            lambda data: len(data["users"]) > 3,
+        ),
+        (
+            dx.lit({"a": 1}).pipe((dx.field("a") == 1) & dx.lit(5)),
+            lambda data: 5,
+        ),
+        (
+            dx.lit({"a": 1}).pipe((dx.field("a") == 2) | dx.lit(None)),
+            lambda data: None,
+        ),
+        (
+            dx.field("missing").not_().not_(),
+            lambda data: False,
+        ),
+        (
+            dx.coalesce(dx.lit(None), dx.field("missing"), dx.lit(5), users),
+            lambda data: 5,
+        ),
+        (
+            users.list.filter(dx.field("active"))
+            .list.map(dx.field("address"))
+            .list.map(dx.field("city")),
+            lambda data: [u["address"]["city"] for u in data["users"] if u["active"]],
+        ),
+        (
+            users.list.length() + users.list.length(),
+            lambda data: 2 * len(data["users"]),
+        ),
+    ]
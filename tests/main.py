@@ -11,7 +11,30 @@ from tests.bench import (
     generate_markdown_table,
     write_markdown_table,
 )
-from tests.cases import Case, build_cases
+from tests.cases import (
+    PARSE_ONLY_CASES,
+    Case,
+    build_cases,
+    build_expected_cases,
+    check_errors,
+    check_expected,
+    check_explained,
+    check_json_ast,
+    check_limits,
+    check_meta,
+    check_ndjson,
+    check_overflow,
+    check_select,
+    check_streamed,
+    check_strict,
+    check_timeout,
+    check_traced,
+    check_parsed,
+    check_pickled,
+    check_rendered_nulls,
+    check_search_many,
+    check_sort_mixed,
+)
 
 README = Path().joinpath("README").with_suffix(".md")
 STUBS = Path().joinpath("dictexprs").with_suffix(".pyi")
@@ -31,6 +54,26 @@ def run() -> None:
     pprint(sample, compact=True, sort_dicts=False)
     print(f"Running {CASES.count()} benchmarks on sample data...")
     CASES.iter().for_each(lambda case: case.check(sample))
+    pc.Seq(PARSE_ONLY_CASES).iter().for_each(lambda query: check_parsed(query, sample))
+    pc.Seq(build_expected_cases()).iter().for_each(
+        lambda case: check_expected(*case, sample)
+    )
+    check_ndjson(sample)
+    check_streamed(sample)
+    check_search_many()
+    check_overflow()
+    check_select(CASES, sample)
+    check_explained()
+    check_traced(CASES, sample)
+    check_strict(CASES, sample)
+    check_errors()
+    check_limits(CASES, sample)
+    check_timeout()
+    check_pickled(CASES, sample)
+    check_json_ast(CASES, sample)
+    check_meta(CASES)
+    check_sort_mixed()
+    check_rendered_nulls()
     print("All benchmark cases passed correctness checks.\n")
     if _update_readme():
         (